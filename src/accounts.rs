@@ -0,0 +1,178 @@
+//! Account index of matrix rows.
+//!
+//! Transposed matrices and CSC views have accounts in columns, so the same index maps their columns.
+//! `csr::CsrCsc` keeps the index along with both views.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use checksum::{ChecksumRead, ChecksumWrite, invalid_data, read_le, read_u64, write_le, write_u64};
+
+/// Binary file magic.
+const MAGIC: &'static [u8] = b"ACC\x00";
+/// Binary file format version.
+const VERSION: u8 = 1;
+/// Maximum number of account IDs preallocated while reading.
+const MAX_PREALLOCATED: usize = 1024 * 1024;
+
+/// Maps rows into account IDs and back.
+#[derive(Clone, Debug)]
+pub struct AccountIndex {
+    /// Maps row index into account ID.
+    row_account: Vec<u32>,
+    /// Maps account ID into the first row with it.
+    account_row: HashMap<u32, usize>,
+}
+
+impl AccountIndex {
+    pub fn new() -> Self {
+        AccountIndex { row_account: Vec::new(), account_row: HashMap::new() }
+    }
+
+    /// Makes an index from account IDs in the row order.
+    pub fn from_ids(account_ids: Vec<u32>) -> Self {
+        let mut index = AccountIndex::new();
+        for account_id in account_ids {
+            index.push(account_id);
+        }
+        index
+    }
+
+    /// Adds the next row. Returns its index.
+    pub fn push(&mut self, account_id: u32) -> usize {
+        let row_index = self.row_account.len();
+        self.row_account.push(account_id);
+        self.account_row.entry(account_id).or_insert(row_index);
+        row_index
+    }
+
+    /// Gets row count.
+    pub fn len(&self) -> usize {
+        self.row_account.len()
+    }
+
+    /// Gets account ID of the row.
+    pub fn get_account_id(&self, row_index: usize) -> u32 {
+        self.row_account[row_index]
+    }
+
+    /// Gets row of the account. The first one if the account appears more than once.
+    pub fn find_row(&self, account_id: u32) -> Option<usize> {
+        self.account_row.get(&account_id).cloned()
+    }
+
+    /// Appends rows of the other index.
+    pub fn append(&mut self, other: AccountIndex) {
+        for account_id in other.row_account {
+            self.push(account_id);
+        }
+    }
+
+    /// Makes an index of the specified rows in the specified order.
+    ///
+    /// Matches `csr::Csr::select_rows` and `csr::Submatrix::rows`.
+    pub fn select(&self, rows: &[usize]) -> AccountIndex {
+        AccountIndex::from_ids(rows.iter().map(|&row_index| self.row_account[row_index]).collect())
+    }
+
+    /// Saves the index to the binary file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut output = BufWriter::new(try!(File::create(path)));
+        try!(self.write(&mut output));
+        output.flush()
+    }
+
+    /// Loads the index from the binary file.
+    pub fn load(path: &Path) -> io::Result<AccountIndex> {
+        AccountIndex::read(&mut BufReader::new(try!(File::open(path))))
+    }
+
+    /// Writes the index in the binary format.
+    ///
+    /// Layout: magic, version, account count, 32-bit account IDs and FNV-1a checksum
+    /// of everything before it like in `csr::Csr::write`. Numbers are little-endian.
+    pub fn write<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let mut output = ChecksumWrite::new(output);
+
+        try!(output.write_all(MAGIC));
+        try!(output.write_all(&[VERSION]));
+        try!(write_u64(&mut output, self.row_account.len() as u64));
+        for &account_id in &self.row_account {
+            try!(write_le(&mut output, account_id as u64, 4));
+        }
+        let checksum = output.checksum();
+        write_u64(&mut output, checksum)
+    }
+
+    /// Reads the index in the binary format.
+    ///
+    /// Fails with `InvalidData` if the header or the checksum are invalid.
+    pub fn read<R: Read>(input: &mut R) -> io::Result<AccountIndex> {
+        let mut input = ChecksumRead::new(input);
+
+        let mut header = [0; 5];
+        try!(input.read_exact(&mut header));
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("invalid magic"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data("unsupported version"));
+        }
+        let account_count = try!(read_u64(&mut input)) as usize;
+        let mut account_ids = Vec::with_capacity(account_count.min(MAX_PREALLOCATED));
+        for _ in 0..account_count {
+            account_ids.push(try!(read_le(&mut input, 4)) as u32);
+        }
+
+        let checksum = input.checksum();
+        if try!(read_u64(&mut input)) != checksum {
+            return Err(invalid_data("checksum mismatch"));
+        }
+
+        Ok(AccountIndex::from_ids(account_ids))
+    }
+}
+
+#[test]
+fn test_push() {
+    let mut index = AccountIndex::new();
+    assert_eq!(index.push(100), 0);
+    assert_eq!(index.push(50), 1);
+    assert_eq!(index.push(100), 2);
+    assert_eq!(index.len(), 3);
+    assert_eq!(index.get_account_id(1), 50);
+    assert_eq!(index.find_row(100), Some(0));
+    assert_eq!(index.find_row(1), None);
+}
+
+#[test]
+fn test_select_append() {
+    let mut index = AccountIndex::from_ids(vec![10, 20, 30]);
+    let selected = index.select(&[2, 0]);
+    assert_eq!(selected.len(), 2);
+    assert_eq!(selected.get_account_id(0), 30);
+    assert_eq!(selected.find_row(10), Some(1));
+    assert_eq!(selected.find_row(20), None);
+
+    index.append(selected);
+    assert_eq!(index.len(), 5);
+    assert_eq!(index.get_account_id(3), 30);
+    assert_eq!(index.find_row(30), Some(2));
+}
+
+#[test]
+fn test_write_read() {
+    let index = AccountIndex::from_ids(vec![10, 4294967295, 10]);
+    let mut buffer = Vec::new();
+    index.write(&mut buffer).unwrap();
+    let read_index = AccountIndex::read(&mut &buffer[..]).unwrap();
+    assert_eq!(read_index.len(), 3);
+    assert_eq!(read_index.get_account_id(1), 4294967295);
+    assert_eq!(read_index.find_row(10), Some(0));
+
+    buffer[13] ^= 1;
+    assert_eq!(AccountIndex::read(&mut &buffer[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    assert!(AccountIndex::read(&mut &buffer[..20]).is_err());
+}
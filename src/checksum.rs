@@ -0,0 +1,122 @@
+//! FNV-1a checksums and little-endian numbers of the binary files.
+
+use std::io::{self, Read, Write};
+
+/// FNV-1a offset basis.
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+/// FNV-1a prime.
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Gets FNV-1a hash of the bytes.
+pub fn hash(bytes: &[u8]) -> u64 {
+    update_checksum(FNV_OFFSET_BASIS, bytes)
+}
+
+/// Updates FNV-1a checksum with the bytes.
+fn update_checksum(checksum: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(checksum, |checksum, &byte| (checksum ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Computes checksum of the written bytes.
+pub struct ChecksumWrite<'a, W: 'a> {
+    output: &'a mut W,
+    checksum: u64,
+}
+
+impl<'a, W: Write> ChecksumWrite<'a, W> {
+    pub fn new(output: &'a mut W) -> Self {
+        ChecksumWrite { output: output, checksum: FNV_OFFSET_BASIS }
+    }
+
+    /// Gets checksum of the bytes written so far.
+    pub fn checksum(&self) -> u64 {
+        self.checksum
+    }
+}
+
+impl<'a, W: Write> Write for ChecksumWrite<'a, W> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let length = try!(self.output.write(buffer));
+        self.checksum = update_checksum(self.checksum, &buffer[..length]);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Computes checksum of the read bytes.
+pub struct ChecksumRead<'a, R: 'a> {
+    input: &'a mut R,
+    checksum: u64,
+}
+
+impl<'a, R: Read> ChecksumRead<'a, R> {
+    pub fn new(input: &'a mut R) -> Self {
+        ChecksumRead { input: input, checksum: FNV_OFFSET_BASIS }
+    }
+
+    /// Gets checksum of the bytes read so far.
+    pub fn checksum(&self) -> u64 {
+        self.checksum
+    }
+}
+
+impl<'a, R: Read> Read for ChecksumRead<'a, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let length = try!(self.input.read(buffer));
+        self.checksum = update_checksum(self.checksum, &buffer[..length]);
+        Ok(length)
+    }
+}
+
+/// Writes little-endian integer of the specified byte length.
+pub fn write_le<W: Write>(output: &mut W, value: u64, length: usize) -> io::Result<()> {
+    let mut buffer = [0; 8];
+    for (index, byte) in buffer.iter_mut().enumerate() {
+        *byte = (value >> (8 * index)) as u8;
+    }
+    output.write_all(&buffer[..length])
+}
+
+/// Reads little-endian integer of the specified byte length.
+pub fn read_le<R: Read>(input: &mut R, length: usize) -> io::Result<u64> {
+    let mut buffer = [0; 8];
+    try!(input.read_exact(&mut buffer[..length]));
+    Ok(buffer[..length].iter().rev().fold(0, |value, &byte| (value << 8) | byte as u64))
+}
+
+pub fn write_u64<W: Write>(output: &mut W, value: u64) -> io::Result<()> {
+    write_le(output, value, 8)
+}
+
+pub fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    read_le(input, 8)
+}
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[test]
+fn test_hash() {
+    assert_eq!(hash(b""), FNV_OFFSET_BASIS);
+    assert_eq!(hash(b"a"), 0xaf63dc4c8601ec8c);
+}
+
+#[test]
+fn test_checksum_write_read() {
+    let mut buffer = Vec::new();
+    {
+        let mut output = ChecksumWrite::new(&mut buffer);
+        write_le(&mut output, 0x0102, 2).unwrap();
+        write_u64(&mut output, 42).unwrap();
+        assert_eq!(output.checksum(), hash(&[0x02, 0x01, 42, 0, 0, 0, 0, 0, 0, 0]));
+    }
+    let mut slice = &buffer[..];
+    let mut input = ChecksumRead::new(&mut slice);
+    assert_eq!(read_le(&mut input, 2).unwrap(), 0x0102);
+    assert_eq!(read_u64(&mut input).unwrap(), 42);
+    assert_eq!(input.checksum(), hash(&buffer));
+}
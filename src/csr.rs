@@ -1,33 +1,115 @@
 //! Compressed Sparse Row (and Compressed Sparse Column) implementation.
+//!
+//! Matrices are generic over column index and value types, e.g. `Csr<u16, f32>` takes 6 bytes
+//! per value instead of 16 bytes of the default `Csr<usize, f64>`.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::u32;
+
+use accounts::AccountIndex;
+use checksum::{ChecksumRead, ChecksumWrite, invalid_data, read_le, read_u64, write_le, write_u64};
+
+/// Binary file magic.
+const MAGIC: &'static [u8] = b"CSR\x00";
+/// Binary file format version.
+const VERSION: u8 = 1;
+/// Maximum number of items preallocated while reading.
+const MAX_PREALLOCATED: usize = 1024 * 1024;
+
+/// Column index type.
+pub trait Index: Copy + Ord + fmt::Debug + Send + 'static {
+    /// Converts the index if it fits into the type.
+    fn try_from_usize(value: usize) -> Option<Self>;
+
+    fn to_usize(self) -> usize;
+
+    /// Converts the index. Panics if it does not fit into the type.
+    fn from_usize(value: usize) -> Self {
+        match Self::try_from_usize(value) {
+            Some(index) => index,
+            None => panic!("index {} is out of range", value),
+        }
+    }
+}
+
+macro_rules! impl_index {
+    ($index:ty) => {
+        impl Index for $index {
+            fn try_from_usize(value: usize) -> Option<Self> {
+                if value as u64 <= <$index>::max_value() as u64 { Some(value as $index) } else { None }
+            }
+
+            fn to_usize(self) -> usize {
+                self as usize
+            }
+        }
+    }
+}
+
+impl_index!(u16);
+impl_index!(u32);
+impl_index!(usize);
+
+/// Value type.
+pub trait Value: Copy + PartialOrd + fmt::Debug + Send + 'static {
+    fn from_f64(value: f64) -> Self;
+
+    fn to_f64(self) -> f64;
+}
+
+impl Value for f32 {
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Value for f64 {
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
 
 /// Value with the corresponding column.
-#[derive(Debug)]
-pub struct ColumnValue {
-    pub column: usize,
-    pub value: f64,
+#[derive(Clone, Copy, Debug)]
+pub struct ColumnValue<C = usize, V = f64> {
+    pub column: C,
+    pub value: V,
 }
 
-pub type Row<'a> = &'a[ColumnValue];
+pub type Row<'a, C = usize, V = f64> = &'a[ColumnValue<C, V>];
 
 /// Compressed Sparse Row matrix.
-#[derive(Debug)]
-pub struct Csr {
-    values: Vec<ColumnValue>,
+#[derive(Clone, Debug)]
+pub struct Csr<C = usize, V = f64> {
+    values: Vec<ColumnValue<C, V>>,
     pointers: Vec<usize>,
 }
 
-/// Value with the corresponding row and column.
-#[derive(Debug)]
-struct RowColumnValue {
-    pub row: usize,
-    pub column_value: ColumnValue,
-}
-
 impl Csr {
+    #[allow(dead_code)]
     pub fn new() -> Self {
+        Csr::default()
+    }
+}
+
+impl<C: Index, V: Value> Default for Csr<C, V> {
+    fn default() -> Self {
         Csr { values: Vec::new(), pointers: Vec::new() }
     }
+}
 
+impl<C: Index, V: Value> Csr<C, V> {
     /// Starts a new row.
     ///
     /// Ensure that you have started a new row after the very last value.
@@ -36,7 +118,7 @@ impl Csr {
     }
 
     /// Adds a new value to the current row.
-    pub fn next(&mut self, column: usize, value: f64) {
+    pub fn next(&mut self, column: C, value: V) {
         self.values.push(ColumnValue { value: value, column: column });
     }
 
@@ -51,42 +133,639 @@ impl Csr {
     }
 
     /// Gets a slice to the row.
-    pub fn get_row(&self, index: usize) -> Row {
+    pub fn get_row(&self, index: usize) -> Row<C, V> {
         assert!(index < self.pointers.len());
         &self.values[self.pointers[index]..self.pointers[index + 1]]
     }
 
-    /// Transposes matrix.
-    pub fn transpose(&mut self) {
-        // Make temporary COO matrix.
-        let mut row_column_values: Vec<RowColumnValue> = Vec::new();
+    /// Gets value at the specified position.
+    ///
+    /// Columns of the row have to be sorted, see `CsrBuilder`.
+    #[allow(dead_code)]
+    pub fn get(&self, row_index: usize, column_index: C) -> Option<V> {
+        let row = self.get_row(row_index);
+        row.binary_search_by(|value| value.column.cmp(&column_index)).ok().map(|index| row[index].value)
+    }
+
+    /// Gets whether columns of each row are sorted and unique.
+    #[allow(dead_code)]
+    pub fn is_sorted(&self) -> bool {
+        (0..self.row_count()).all(|row_index| {
+            let row = self.get_row(row_index);
+            row.iter().zip(row.iter().skip(1)).all(|(a, b)| a.column < b.column)
+        })
+    }
+
+    /// Sorts columns of each row.
+    pub fn sort_rows(&mut self) {
+        for row_index in 0..self.row_count() {
+            self.values[self.pointers[row_index]..self.pointers[row_index + 1]].sort_by(|a, b| a.column.cmp(&b.column));
+        }
+    }
+
+    /// Merges values of the corresponding rows of the other matrix with the same row count.
+    ///
+    /// Rows of both matrices have to be sorted and must not share columns. Rows of the result are sorted.
+    pub fn merge_rows(&self, other: &Csr<C, V>) -> Csr<C, V> {
+        assert_eq!(self.row_count(), other.row_count());
+
+        let mut result = CsrBuilder::default();
+        for row_index in 0..self.row_count() {
+            let (mut a, mut b) = (self.get_row(row_index), other.get_row(row_index));
+            while !a.is_empty() || !b.is_empty() {
+                let value = if b.is_empty() || (!a.is_empty() && a[0].column < b[0].column) {
+                    let value = a[0];
+                    a = &a[1..];
+                    value
+                } else {
+                    let value = b[0];
+                    b = &b[1..];
+                    value
+                };
+                result.next(value.column, value.value);
+            }
+            result.end_row();
+        }
+        result.build()
+    }
+
+    /// Appends rows of the other matrix.
+    ///
+    /// Both matrices have to be finalized with `start`.
+    pub fn append(&mut self, other: Csr<C, V>) {
+        if self.pointers.is_empty() {
+            *self = other;
+            return;
+        }
+        if other.pointers.is_empty() {
+            return;
+        }
+        let offset = self.values.len();
+        self.values.extend(other.values.into_iter());
+        self.pointers.extend(other.pointers[1..].iter().map(|pointer| pointer + offset));
+    }
+
+    /// Replaces column indexes with the mapped ones.
+    ///
+    /// Use `sort_rows` afterwards if the mapping does not preserve the column order.
+    pub fn map_columns<F: Fn(usize) -> usize>(&mut self, f: F) {
+        for value in &mut self.values {
+            value.column = C::from_usize(f(value.column.to_usize()));
+        }
+    }
+
+    /// Converts the matrix to other column index and value types.
+    #[allow(dead_code)]
+    pub fn convert<C2: Index, V2: Value>(&self) -> Csr<C2, V2> {
+        Csr {
+            values: self.values.iter().map(|value| ColumnValue {
+                column: C2::from_usize(value.column.to_usize()),
+                value: V2::from_f64(value.value.to_f64()),
+            }).collect(),
+            pointers: self.pointers.clone(),
+        }
+    }
+
+    /// Saves the matrix to the binary file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut output = BufWriter::new(try!(File::create(path)));
+        try!(self.write(&mut output));
+        output.flush()
+    }
+
+    /// Loads the matrix from the binary file.
+    pub fn load(path: &Path) -> io::Result<Csr<C, V>> {
+        Csr::read(&mut BufReader::new(try!(File::open(path))))
+    }
+
+    /// Writes the matrix in the binary format.
+    ///
+    /// Layout: magic, version, pointer count, value count, 64-bit pointers, 32-bit columns,
+    /// 64-bit values and FNV-1a checksum of everything before it. Numbers are little-endian.
+    /// The layout does not depend on the column index and value types.
+    pub fn write<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let mut output = ChecksumWrite::new(output);
+
+        try!(output.write_all(MAGIC));
+        try!(output.write_all(&[VERSION]));
+        try!(write_u64(&mut output, self.pointers.len() as u64));
+        try!(write_u64(&mut output, self.values.len() as u64));
+        for &pointer in &self.pointers {
+            try!(write_u64(&mut output, pointer as u64));
+        }
+        for value in &self.values {
+            if value.column.to_usize() > u32::MAX as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "column is out of range"));
+            }
+            try!(write_le(&mut output, value.column.to_usize() as u64, 4));
+        }
+        for value in &self.values {
+            try!(write_u64(&mut output, value.value.to_f64().to_bits()));
+        }
+        let checksum = output.checksum();
+        write_u64(&mut output, checksum)
+    }
+
+    /// Reads the matrix in the binary format.
+    ///
+    /// Fails with `InvalidData` if the header, the pointers or the checksum are invalid
+    /// or a column does not fit into the column index type. Pointers must start with zero.
+    pub fn read<R: Read>(input: &mut R) -> io::Result<Csr<C, V>> {
+        let mut input = ChecksumRead::new(input);
+
+        let mut header = [0; 5];
+        try!(input.read_exact(&mut header));
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("invalid magic"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data("unsupported version"));
+        }
+        let pointer_count = try!(read_u64(&mut input)) as usize;
+        let value_count = try!(read_u64(&mut input)) as usize;
+        if pointer_count == 0 {
+            return Err(invalid_data("no row pointers"));
+        }
+
+        let mut pointers = Vec::with_capacity(pointer_count.min(MAX_PREALLOCATED));
+        for _ in 0..pointer_count {
+            let pointer = try!(read_u64(&mut input)) as usize;
+            if pointer > value_count || pointers.last().map_or(pointer != 0, |&last| pointer < last) {
+                return Err(invalid_data("invalid row pointer"));
+            }
+            pointers.push(pointer);
+        }
+        if pointers[pointer_count - 1] != value_count {
+            return Err(invalid_data("value count mismatch"));
+        }
+        let mut columns = Vec::with_capacity(value_count.min(MAX_PREALLOCATED));
+        for _ in 0..value_count {
+            let column = try!(read_le(&mut input, 4)) as usize;
+            columns.push(try!(C::try_from_usize(column).ok_or(invalid_data("column is out of range"))));
+        }
+        let mut values = Vec::with_capacity(value_count.min(MAX_PREALLOCATED));
+        for column in columns {
+            values.push(ColumnValue { column: column, value: V::from_f64(f64::from_bits(try!(read_u64(&mut input)))) });
+        }
+
+        let checksum = input.checksum();
+        if try!(read_u64(&mut input)) != checksum {
+            return Err(invalid_data("checksum mismatch"));
+        }
+
+        Ok(Csr { values: values, pointers: pointers })
+    }
+
+    /// Gets column count, i.e. maximum column index plus one.
+    pub fn column_count(&self) -> usize {
+        self.values.iter().map(|value| value.column.to_usize() + 1).max().unwrap_or(0)
+    }
+
+    /// Gets mean value of each row. `None` for empty rows.
+    #[allow(dead_code)]
+    pub fn row_means(&self) -> Vec<Option<f64>> {
+        (0..self.row_count()).map(|row_index| {
+            let row = self.get_row(row_index);
+            if row.is_empty() { None } else { Some(row.iter().fold(0.0, |sum, value| sum + value.value.to_f64()) / row.len() as f64) }
+        }).collect()
+    }
+
+    /// Gets mean value of each column. `None` for empty columns.
+    #[allow(dead_code)]
+    pub fn column_means(&self, column_count: usize) -> Vec<Option<f64>> {
+        let mut sums = vec![0.0; column_count];
+        let mut counts = vec![0; column_count];
+        for value in &self.values {
+            sums[value.column.to_usize()] += value.value.to_f64();
+            counts[value.column.to_usize()] += 1;
+        }
+        sums.into_iter().zip(counts).map(|(sum, count)| if count != 0 { Some(sum / count as f64) } else { None }).collect()
+    }
+
+    /// Gets Euclidean norm of each row.
+    #[allow(dead_code)]
+    pub fn row_norms(&self) -> Vec<f64> {
+        (0..self.row_count()).map(|row_index| {
+            self.get_row(row_index).iter().fold(0.0, |sum, value| sum + value.value.to_f64().powi(2)).sqrt()
+        }).collect()
+    }
+
+    /// Gets Euclidean norm of each column.
+    #[allow(dead_code)]
+    pub fn column_norms(&self, column_count: usize) -> Vec<f64> {
+        let mut squared_norms = vec![0.0; column_count];
+        for value in &self.values {
+            squared_norms[value.column.to_usize()] += value.value.to_f64().powi(2);
+        }
+        squared_norms.into_iter().map(f64::sqrt).collect()
+    }
+
+    /// Subtracts row means from the values.
+    ///
+    /// Returns the subtracted means.
+    #[allow(dead_code)]
+    pub fn center_rows(&mut self) -> Vec<Option<f64>> {
+        let means = self.row_means();
+        for row_index in 0..self.row_count() {
+            if let Some(mean) = means[row_index] {
+                for value in &mut self.values[self.pointers[row_index]..self.pointers[row_index + 1]] {
+                    value.value = V::from_f64(value.value.to_f64() - mean);
+                }
+            }
+        }
+        means
+    }
+
+    /// Subtracts column means from the values.
+    ///
+    /// Returns the subtracted means.
+    #[allow(dead_code)]
+    pub fn center_columns(&mut self, column_count: usize) -> Vec<Option<f64>> {
+        let means = self.column_means(column_count);
+        for value in &mut self.values {
+            value.value = V::from_f64(value.value.to_f64() - means[value.column.to_usize()].unwrap());
+        }
+        means
+    }
+
+    /// Multiplies the matrix by the dense vector.
+    #[allow(dead_code)]
+    pub fn mul_vector(&self, vector: &[f64]) -> Vec<f64> {
+        (0..self.row_count()).map(|row_index| {
+            self.get_row(row_index).iter().fold(0.0, |sum, value| sum + value.value.to_f64() * vector[value.column.to_usize()])
+        }).collect()
+    }
+
+    /// Multiplies the matrix by the other sparse matrix.
+    ///
+    /// Rows of the other matrix correspond to columns of this one. Rows of the result are sorted.
+    #[allow(dead_code)]
+    pub fn mul<C2: Index>(&self, other: &Csr<C2, V>, other_column_count: usize) -> Csr<C2, V> {
+        let mut result = CsrBuilder::default();
+        // Dense accumulator of the current row and its non-zero columns.
+        let mut accumulator = vec![0.0; other_column_count];
+        let mut is_used = vec![false; other_column_count];
+        let mut columns = Vec::new();
+
+        for row_index in 0..self.row_count() {
+            for value in self.get_row(row_index) {
+                for other_value in other.get_row(value.column.to_usize()) {
+                    let column = other_value.column.to_usize();
+                    if !is_used[column] {
+                        is_used[column] = true;
+                        columns.push(column);
+                    }
+                    accumulator[column] += value.value.to_f64() * other_value.value.to_f64();
+                }
+            }
+            columns.sort();
+            for &column in &columns {
+                result.next(C2::from_usize(column), V::from_f64(accumulator[column]));
+                accumulator[column] = 0.0;
+                is_used[column] = false;
+            }
+            columns.clear();
+            result.end_row();
+        }
+
+        result.build()
+    }
+
+    /// Gets co-occurrence counts of columns, i.e. number of rows that have both columns.
+    ///
+    /// Returns square matrix, the diagonal holds number of rows that have the column.
+    #[allow(dead_code)]
+    pub fn co_occurrence(&self, column_count: usize) -> Csr<C, V> {
+        let mut pattern = self.map(|_| V::from_f64(1.0));
+        pattern.sort_rows();
+        pattern.transposed(column_count).mul(&pattern, column_count)
+    }
+
+    /// Replaces each value with the function of it.
+    #[allow(dead_code)]
+    pub fn map_values<F: Fn(V) -> V>(&mut self, f: F) {
+        for value in &mut self.values {
+            value.value = f(value.value);
+        }
+    }
+
+    /// Replaces each value with the function of its row, column and the value itself.
+    #[allow(dead_code)]
+    pub fn map_entries<F: Fn(usize, C, V) -> V>(&mut self, f: F) {
+        for row_index in 0..self.row_count() {
+            for value in &mut self.values[self.pointers[row_index]..self.pointers[row_index + 1]] {
+                value.value = f(row_index, value.column, value.value);
+            }
+        }
+    }
+
+    /// Makes a copy with the function applied to each value.
+    pub fn map<F: Fn(V) -> V>(&self, f: F) -> Csr<C, V> {
+        Csr {
+            values: self.values.iter().map(|value| ColumnValue { column: value.column, value: f(value.value) }).collect(),
+            pointers: self.pointers.clone(),
+        }
+    }
+
+    /// Makes a transposed copy with the specified number of rows.
+    ///
+    /// Uses counting sort, so it takes O(values + columns). Rows of the result are sorted.
+    /// Columns of the result are row indexes, so they are always `usize`.
+    /// Use `CsrCsc` to keep the account index of the rows along with the transposed copy.
+    pub fn transposed(&self, column_count: usize) -> Csr<usize, V> {
+        // Count values in each column.
+        let mut pointers = vec![0; column_count + 1];
+        for value in &self.values {
+            assert!(value.column.to_usize() < column_count, "{:?} < {}", value.column, column_count);
+            pointers[value.column.to_usize() + 1] += 1;
+        }
+        // Turn counts into row starts of the transposed matrix.
+        for column in 0..column_count {
+            pointers[column + 1] += pointers[column];
+        }
+        // Scatter values into their positions.
+        let mut positions = pointers.clone();
+        let mut values: Vec<ColumnValue<usize, V>> = self.values.iter().map(|value| ColumnValue { column: 0, value: value.value }).collect();
         for row_index in 0..self.row_count() {
             for column_value in self.get_row(row_index) {
-                row_column_values.push(RowColumnValue {
-                    row: row_index,
-                    column_value: ColumnValue { value: column_value.value, column: column_value.column },
-                });
+                let position = &mut positions[column_value.column.to_usize()];
+                values[*position] = ColumnValue { column: row_index, value: column_value.value };
+                *position += 1;
             }
         }
-        // Drop this matrix.
-        self.values.clear();
-        self.pointers = Vec::new();
-        // Sort the COO matrix by column then by row.
-        row_column_values.sort_by(|a, b| (a.column_value.column, a.row).cmp(&(b.column_value.column, b.row)));
-        // Reconstruct this matrix as a transposed one. Group by column index.
-        let mut current_column = 0;
-        // Start the first row.
-        self.start();
-        // Push values.
-        for row_column_value in row_column_values {
-            while current_column != row_column_value.column_value.column {
-                current_column += 1;
-                self.start();
+        Csr { values: values, pointers: pointers }
+    }
+
+    /// Makes a matrix of the specified rows in the specified order.
+    ///
+    /// Row `i` of the result is row `rows[i]` of this matrix, so `rows` is the map back to the original rows.
+    pub fn select_rows(&self, rows: &[usize]) -> Csr<C, V> {
+        let mut matrix = Csr::default();
+        for &row_index in rows {
+            matrix.start();
+            matrix.values.extend(self.get_row(row_index).iter().cloned());
+        }
+        matrix.start();
+        matrix
+    }
+
+    /// Makes a copy without the columns for which the predicate returns `true`.
+    ///
+    /// Remaining columns are renumbered keeping their order.
+    /// Returns the matrix and the map from its columns back to the original columns.
+    pub fn drop_columns<F: Fn(usize) -> bool>(&self, column_count: usize, is_dropped: F) -> (Csr<C, V>, Vec<usize>) {
+        let columns: Vec<usize> = (0..column_count).filter(|&column| !is_dropped(column)).collect();
+        let mut new_columns = vec![None; column_count];
+        for (new_column, &column) in columns.iter().enumerate() {
+            new_columns[column] = Some(new_column);
+        }
+
+        let mut matrix = Csr::default();
+        for row_index in 0..self.row_count() {
+            matrix.start();
+            for value in self.get_row(row_index) {
+                if let Some(new_column) = new_columns[value.column.to_usize()] {
+                    matrix.next(C::from_usize(new_column), value.value);
+                }
+            }
+        }
+        matrix.start();
+
+        (matrix, columns)
+    }
+
+    /// Prunes the matrix to its k-core.
+    ///
+    /// Rows with less than `min_row_length` values and columns with less than `min_column_length` values
+    /// are removed repeatedly until every remaining row and column has enough values.
+    pub fn k_core(&self, column_count: usize, min_row_length: usize, min_column_length: usize) -> Submatrix<C, V> {
+        let mut is_row_kept = vec![true; self.row_count()];
+        let mut is_column_kept = vec![true; column_count];
+
+        loop {
+            let mut row_lengths = vec![0; self.row_count()];
+            let mut column_lengths = vec![0; column_count];
+            for row_index in (0..self.row_count()).filter(|&row_index| is_row_kept[row_index]) {
+                for value in self.get_row(row_index) {
+                    let column = value.column.to_usize();
+                    if is_column_kept[column] {
+                        row_lengths[row_index] += 1;
+                        column_lengths[column] += 1;
+                    }
+                }
+            }
+
+            let mut is_changed = false;
+            for (is_kept, &length) in is_row_kept.iter_mut().zip(&row_lengths) {
+                if *is_kept && length < min_row_length {
+                    *is_kept = false;
+                    is_changed = true;
+                }
+            }
+            for (is_kept, &length) in is_column_kept.iter_mut().zip(&column_lengths) {
+                if *is_kept && length < min_column_length {
+                    *is_kept = false;
+                    is_changed = true;
+                }
+            }
+            if !is_changed {
+                break;
             }
-            self.next(row_column_value.row, row_column_value.column_value.value);
         }
-        // Finalize matrix.
-        self.start();
+
+        let rows: Vec<usize> = (0..self.row_count()).filter(|&row_index| is_row_kept[row_index]).collect();
+        let (matrix, columns) = self.select_rows(&rows).drop_columns(column_count, |column| !is_column_kept[column]);
+        Submatrix { matrix: matrix, rows: rows, columns: columns }
+    }
+}
+
+impl<V: Value> Csr<usize, V> {
+    /// Transposes matrix.
+    pub fn transpose(&mut self) {
+        let column_count = self.column_count();
+        *self = self.transposed(column_count);
+    }
+}
+
+/// Part of a matrix with maps back to the original rows and columns.
+#[derive(Debug)]
+pub struct Submatrix<C = usize, V = f64> {
+    pub matrix: Csr<C, V>,
+    /// Maps row index into the original row index.
+    pub rows: Vec<usize>,
+    /// Maps column index into the original column index.
+    pub columns: Vec<usize>,
+}
+
+/// Builds matrix with sorted unique columns in each row.
+pub struct CsrBuilder<C = usize, V = f64> {
+    matrix: Csr<C, V>,
+    /// Last column of the current row.
+    last_column: Option<C>,
+}
+
+impl CsrBuilder {
+    /// Makes a builder with the first row started.
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        CsrBuilder::default()
+    }
+}
+
+impl<C: Index, V: Value> Default for CsrBuilder<C, V> {
+    fn default() -> Self {
+        let mut matrix = Csr::default();
+        matrix.start();
+        CsrBuilder { matrix: matrix, last_column: None }
+    }
+}
+
+impl<C: Index, V: Value> CsrBuilder<C, V> {
+    /// Adds a new value to the current row.
+    ///
+    /// Panics unless the column is greater than the previous one in the row.
+    pub fn next(&mut self, column: C, value: V) {
+        if let Some(last_column) = self.last_column {
+            assert!(last_column < column, "columns must be sorted and unique: {:?} after {:?}", column, last_column);
+        }
+        self.last_column = Some(column);
+        self.matrix.next(column, value);
+    }
+
+    /// Ends the current row and starts a new one.
+    pub fn end_row(&mut self) {
+        self.matrix.start();
+        self.last_column = None;
+    }
+
+    /// Gets value count.
+    pub fn len(&self) -> usize {
+        self.matrix.len()
+    }
+
+    /// Gets count of the ended rows.
+    #[allow(dead_code)]
+    pub fn row_count(&self) -> usize {
+        self.matrix.row_count()
+    }
+
+    /// Gets the matrix of the ended rows.
+    ///
+    /// Panics if the current row is not empty.
+    pub fn build(self) -> Csr<C, V> {
+        assert!(self.last_column.is_none(), "the last row is not ended");
+        self.matrix
+    }
+}
+
+/// Iterator over columns present in both rows.
+///
+/// Yields column and values from both rows. Both rows have to be sorted.
+#[allow(dead_code)]
+pub struct Intersection<'a, C: 'a = usize, V: 'a = f64> {
+    a: Row<'a, C, V>,
+    b: Row<'a, C, V>,
+}
+
+impl<'a, C: Index, V: Value> Iterator for Intersection<'a, C, V> {
+    type Item = (C, V, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.a.is_empty() && !self.b.is_empty() {
+            let (a, b) = (&self.a[0], &self.b[0]);
+            if a.column < b.column {
+                self.a = &self.a[1..];
+            } else if a.column > b.column {
+                self.b = &self.b[1..];
+            } else {
+                self.a = &self.a[1..];
+                self.b = &self.b[1..];
+                return Some((a.column, a.value, b.value));
+            }
+        }
+        None
+    }
+}
+
+/// Intersects sorted rows by column.
+#[allow(dead_code)]
+pub fn intersect<'a, C: Index, V: Value>(a: Row<'a, C, V>, b: Row<'a, C, V>) -> Intersection<'a, C, V> {
+    Intersection { a: a, b: b }
+}
+
+/// Gets count of columns present in both sorted rows.
+#[allow(dead_code)]
+pub fn intersection_count<C: Index, V: Value>(a: Row<C, V>, b: Row<C, V>) -> usize {
+    intersect(a, b).count()
+}
+
+/// Ratings in both row-oriented (CSR) and column-oriented (CSC) form.
+///
+/// Rows of the CSC view are columns of the original matrix, i.e. values of `get_column` hold row indexes.
+/// The account index of the rows is kept along, so it maps both the rows and the CSC columns into accounts.
+pub struct CsrCsc<C = usize, V = f64> {
+    csr: Csr<C, V>,
+    csc: Csr<usize, V>,
+    accounts: AccountIndex,
+}
+
+impl<C: Index, V: Value> CsrCsc<C, V> {
+    /// Makes the column-oriented view of the matrix with the specified number of columns.
+    ///
+    /// The account index must have an account for each row.
+    #[allow(dead_code)]
+    pub fn new(csr: Csr<C, V>, column_count: usize, accounts: AccountIndex) -> Self {
+        assert_eq!(accounts.len(), csr.row_count());
+        let csc = csr.transposed(column_count);
+        CsrCsc { csr: csr, csc: csc, accounts: accounts }
+    }
+
+    /// Gets the row-oriented view.
+    #[allow(dead_code)]
+    pub fn csr(&self) -> &Csr<C, V> {
+        &self.csr
+    }
+
+    /// Gets the column-oriented view.
+    #[allow(dead_code)]
+    pub fn csc(&self) -> &Csr<usize, V> {
+        &self.csc
+    }
+
+    /// Gets the account index of the rows and of the CSC columns.
+    #[allow(dead_code)]
+    pub fn accounts(&self) -> &AccountIndex {
+        &self.accounts
+    }
+
+    /// Gets row count.
+    #[allow(dead_code)]
+    pub fn row_count(&self) -> usize {
+        self.csr.row_count()
+    }
+
+    /// Gets column count.
+    #[allow(dead_code)]
+    pub fn column_count(&self) -> usize {
+        self.csc.row_count()
+    }
+
+    /// Gets values of the row.
+    #[allow(dead_code)]
+    pub fn get_row(&self, index: usize) -> Row<C, V> {
+        self.csr.get_row(index)
+    }
+
+    /// Gets values of the column. Their `column` is the row index.
+    #[allow(dead_code)]
+    pub fn get_column(&self, index: usize) -> Row<usize, V> {
+        self.csc.get_row(index)
+    }
+
+    /// Gets the row-oriented matrix and its account index back.
+    #[allow(dead_code)]
+    pub fn into_csr(self) -> (Csr<C, V>, AccountIndex) {
+        (self.csr, self.accounts)
     }
 }
 
@@ -153,6 +832,258 @@ fn test_get_row() {
     assert_eq!(matrix.get_row(1)[0].column, 2);
 }
 
+#[test]
+fn test_get() {
+    let mut builder = CsrBuilder::new();
+    builder.next(1, 1.0);
+    builder.next(4, 2.0);
+    builder.next(9, 3.0);
+    builder.end_row();
+    builder.end_row();
+    let matrix = builder.build();
+
+    assert_eq!(matrix.row_count(), 2);
+    assert!(matrix.is_sorted());
+    assert_eq!(matrix.get(0, 4), Some(2.0));
+    assert_eq!(matrix.get(0, 9), Some(3.0));
+    assert_eq!(matrix.get(0, 5), None);
+    assert_eq!(matrix.get(1, 1), None);
+}
+
+#[test]
+#[should_panic]
+fn test_builder_unsorted() {
+    let mut builder = CsrBuilder::new();
+    builder.next(2, 1.0);
+    builder.next(2, 2.0);
+}
+
+#[test]
+fn test_sort_rows() {
+    let mut matrix = Csr::new();
+    matrix.start();
+    matrix.next(5, 1.0);
+    matrix.next(2, 2.0);
+    matrix.start();
+    matrix.next(0, 3.0);
+    matrix.start();
+
+    assert!(!matrix.is_sorted());
+    matrix.sort_rows();
+    assert!(matrix.is_sorted());
+    assert_eq!(matrix.get(0, 5), Some(1.0));
+    assert_eq!(matrix.get_row(0)[0].column, 2);
+}
+
+#[test]
+fn test_intersect() {
+    let mut builder = CsrBuilder::new();
+    for &column in &[0, 2, 3, 7] {
+        builder.next(column, column as f64);
+    }
+    builder.end_row();
+    for &column in &[1, 2, 7, 8] {
+        builder.next(column, -(column as f64));
+    }
+    builder.end_row();
+    let matrix = builder.build();
+
+    assert_eq!(intersect(matrix.get_row(0), matrix.get_row(1)).collect::<Vec<(usize, f64, f64)>>(), vec![(2, 2.0, -2.0), (7, 7.0, -7.0)]);
+    assert_eq!(intersection_count(matrix.get_row(1), matrix.get_row(0)), 2);
+    assert_eq!(intersection_count(matrix.get_row(0), &[]), 0);
+}
+
+#[test]
+fn test_append() {
+    let mut matrix = Csr::new();
+    matrix.start();
+    matrix.next(0, 1.0);
+    matrix.start();
+    matrix.next(2, 2.0);
+    matrix.start();
+
+    let mut other = Csr::new();
+    other.start();
+    other.start();
+    other.next(1, 3.0);
+    other.next(3, 4.0);
+    other.start();
+
+    matrix.append(other);
+
+    assert_eq!(matrix.row_count(), 4);
+    assert_eq!(matrix.pointers, vec![0, 1, 2, 2, 4]);
+    assert_eq!(matrix.get_row(3)[1].column, 3);
+    assert_eq!(matrix.get_row(3)[1].value, 4.0);
+}
+
+#[test]
+fn test_map_columns() {
+    let mut matrix = Csr::new();
+    matrix.start();
+    matrix.next(0, 1.0);
+    matrix.next(2, 2.0);
+    matrix.start();
+
+    matrix.map_columns(|column| if column >= 2 { column + 3 } else { column });
+
+    assert_eq!(matrix.values.iter().map(|value| value.column).collect::<Vec<usize>>(), vec![0, 5]);
+}
+
+#[test]
+fn test_write_read() {
+    use std::io::Cursor;
+
+    let mut matrix = Csr::new();
+    matrix.start();
+    matrix.next(0, 1.5);
+    matrix.start();
+    matrix.start();
+    matrix.next(7, -2.25);
+    matrix.next(300, 99.0);
+    matrix.start();
+
+    let mut buffer = Vec::new();
+    matrix.write(&mut buffer).unwrap();
+    assert_eq!(buffer.len(), 5 + 8 * (2 + 4) + 4 * 3 + 8 * (3 + 1));
+
+    let other = Csr::read(&mut Cursor::new(&buffer)).unwrap();
+    assert_eq!(other.pointers, matrix.pointers);
+    assert_eq!(other.values.iter().map(|value| value.column).collect::<Vec<usize>>(), vec![0, 7, 300]);
+    assert_eq!(other.values.iter().map(|value| value.value).collect::<Vec<f64>>(), vec![1.5, -2.25, 99.0]);
+    assert_eq!(other.column_count(), 301);
+
+    let mut corrupt = buffer.clone();
+    corrupt[60] ^= 1;
+    assert_eq!(Csr::<usize, f64>::read(&mut Cursor::new(&corrupt)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    assert_eq!(Csr::<usize, f64>::read(&mut Cursor::new(&buffer[..buffer.len() - 1])).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(Csr::<usize, f64>::read(&mut Cursor::new(b"CSV\x00\x01")).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    // Matrix without row pointers has no valid row count.
+    let mut empty = Vec::new();
+    {
+        let mut output = ChecksumWrite::new(&mut empty);
+        output.write_all(MAGIC).unwrap();
+        output.write_all(&[VERSION]).unwrap();
+        write_u64(&mut output, 0).unwrap();
+        write_u64(&mut output, 0).unwrap();
+        let checksum = output.checksum();
+        write_u64(&mut output, checksum).unwrap();
+    }
+    assert_eq!(Csr::<usize, f64>::read(&mut Cursor::new(&empty)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_convert() {
+    use std::io::Cursor;
+
+    let mut matrix = Csr::new();
+    matrix.start();
+    matrix.next(0, 1.5);
+    matrix.next(300, 0.1);
+    matrix.start();
+
+    let other: Csr<u16, f32> = matrix.convert();
+    assert_eq!(other.get(0, 300), Some(0.1f32));
+    assert_eq!(other.get(0, 1), None);
+    assert_eq!(other.column_count(), 301);
+    assert_eq!(other.row_means(), vec![Some((1.5f32 as f64 + 0.1f32 as f64) / 2.0)]);
+
+    let mut buffer = Vec::new();
+    other.write(&mut buffer).unwrap();
+    let read: Csr<u16, f32> = Csr::read(&mut Cursor::new(&buffer)).unwrap();
+    assert_eq!(read.values.iter().map(|value| value.column).collect::<Vec<u16>>(), vec![0, 300]);
+
+    matrix.map_columns(|column| column * 1000);
+    let mut buffer = Vec::new();
+    matrix.write(&mut buffer).unwrap();
+    assert_eq!(Csr::<u16, f32>::read(&mut Cursor::new(&buffer)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_index() {
+    assert_eq!(u16::try_from_usize(65535), Some(65535));
+    assert_eq!(u16::try_from_usize(65536), None);
+    assert_eq!(usize::from_usize(70000), 70000);
+}
+
+#[test]
+#[should_panic]
+fn test_index_out_of_range() {
+    u16::from_usize(65536);
+}
+
+#[cfg(test)]
+fn make_matrix(rows: &[&[(usize, f64)]]) -> Csr {
+    let mut builder = CsrBuilder::new();
+    for row in rows {
+        for &(column, value) in *row {
+            builder.next(column, value);
+        }
+        builder.end_row();
+    }
+    builder.build()
+}
+
+#[test]
+fn test_means_and_norms() {
+    let matrix = make_matrix(&[&[(0, 3.0), (2, 4.0)], &[], &[(2, 2.0)]]);
+
+    assert_eq!(matrix.row_means(), vec![Some(3.5), None, Some(2.0)]);
+    assert_eq!(matrix.column_means(4), vec![Some(3.0), None, Some(3.0), None]);
+    assert_eq!(matrix.row_norms(), vec![5.0, 0.0, 2.0]);
+    assert_eq!(matrix.column_norms(3), vec![3.0, 0.0, 20.0f64.sqrt()]);
+}
+
+#[test]
+fn test_center() {
+    let mut matrix = make_matrix(&[&[(0, 3.0), (2, 4.0)], &[(2, 2.0)]]);
+    matrix.center_rows();
+    assert_eq!(matrix.values.iter().map(|value| value.value).collect::<Vec<f64>>(), vec![-0.5, 0.5, 0.0]);
+
+    let mut matrix = make_matrix(&[&[(0, 3.0), (2, 4.0)], &[(2, 2.0)]]);
+    assert_eq!(matrix.center_columns(3), vec![Some(3.0), None, Some(3.0)]);
+    assert_eq!(matrix.values.iter().map(|value| value.value).collect::<Vec<f64>>(), vec![0.0, 1.0, -1.0]);
+}
+
+#[test]
+fn test_mul() {
+    let a = make_matrix(&[&[(0, 1.0), (1, 2.0)], &[(1, 3.0)]]);
+    let b = make_matrix(&[&[(1, 4.0)], &[(0, 5.0), (1, 6.0)]]);
+
+    assert_eq!(a.mul_vector(&[1.0, 10.0]), vec![21.0, 30.0]);
+
+    let product = a.mul(&b, 2);
+    assert_eq!(product.pointers, vec![0, 2, 4]);
+    assert_eq!(product.get(0, 0), Some(10.0));
+    assert_eq!(product.get(0, 1), Some(16.0));
+    assert_eq!(product.get(1, 0), Some(15.0));
+    assert_eq!(product.get(1, 1), Some(18.0));
+}
+
+#[test]
+fn test_co_occurrence() {
+    let matrix = make_matrix(&[&[(0, 50.0), (2, 40.0)], &[(0, 60.0), (1, 70.0), (2, 30.0)], &[(1, 10.0)]]);
+    let counts = matrix.co_occurrence(3);
+
+    assert_eq!(counts.row_count(), 3);
+    assert_eq!(counts.get(0, 0), Some(2.0));
+    assert_eq!(counts.get(0, 1), Some(1.0));
+    assert_eq!(counts.get(0, 2), Some(2.0));
+    assert_eq!(counts.get(1, 1), Some(2.0));
+    assert_eq!(counts.get(2, 1), Some(1.0));
+}
+
+#[test]
+fn test_map() {
+    let mut matrix = make_matrix(&[&[(0, 1.0), (2, 2.0)], &[(1, 3.0)]]);
+    assert_eq!(matrix.map(|value| value * 2.0).get(1, 1), Some(6.0));
+    matrix.map_values(|value| -value);
+    assert_eq!(matrix.get(0, 2), Some(-2.0));
+    matrix.map_entries(|row_index, column, value| value + (10 * row_index + column) as f64);
+    assert_eq!(matrix.values.iter().map(|value| value.value).collect::<Vec<f64>>(), vec![-1.0, 0.0, 8.0]);
+}
+
 #[test]
 fn test_transpose() {
     let mut matrix = Csr::new();
@@ -171,3 +1102,98 @@ fn test_transpose() {
     assert_eq!(matrix.values.iter().map(|value| value.column).collect::<Vec<usize>>(), vec![0, 2, 1, 1]);
     assert_eq!(matrix.values.iter().map(|value| value.value).collect::<Vec<f64>>(), vec![1.0, 7.0, 2.0, 3.0]);
 }
+
+#[test]
+fn test_transposed() {
+    let mut matrix = Csr::new();
+    matrix.start();
+    matrix.next(1, 1.0);
+    matrix.next(0, 2.0);
+    matrix.start();
+    matrix.start();
+    matrix.next(1, 3.0);
+    matrix.start();
+
+    let transposed = matrix.transposed(3);
+
+    assert_eq!(transposed.pointers, vec![0, 1, 3, 3]);
+    assert_eq!(transposed.values.iter().map(|value| value.column).collect::<Vec<usize>>(), vec![0, 0, 2]);
+    assert_eq!(transposed.values.iter().map(|value| value.value).collect::<Vec<f64>>(), vec![2.0, 1.0, 3.0]);
+    assert_eq!(transposed.transposed(3).pointers, matrix.pointers);
+}
+
+#[test]
+fn test_merge_rows() {
+    let a = make_matrix(&[&[(0, 1.0), (3, 4.0)], &[]]);
+    let b = make_matrix(&[&[(1, 2.0)], &[(2, 3.0)]]);
+    let merged = a.merge_rows(&b);
+    assert_eq!(merged.pointers, vec![0, 3, 4]);
+    assert_eq!(merged.values.iter().map(|value| value.column).collect::<Vec<usize>>(), vec![0, 1, 3, 2]);
+    assert_eq!(merged.get(0, 1), Some(2.0));
+}
+
+#[test]
+fn test_select_rows() {
+    let matrix = make_matrix(&[&[(0, 1.0)], &[(1, 2.0), (2, 3.0)], &[]]);
+    let selected = matrix.select_rows(&[2, 1, 1]);
+    assert_eq!(selected.row_count(), 3);
+    assert!(selected.get_row(0).is_empty());
+    assert_eq!(selected.get(1, 2), Some(3.0));
+    assert_eq!(selected.get(2, 1), Some(2.0));
+    assert_eq!(matrix.select_rows(&[]).row_count(), 0);
+}
+
+#[test]
+fn test_drop_columns() {
+    let matrix = make_matrix(&[&[(0, 1.0), (3, 4.0)], &[(1, 2.0), (2, 3.0)]]);
+    let (dropped, columns) = matrix.drop_columns(4, |column| column == 1);
+    assert_eq!(columns, vec![0, 2, 3]);
+    assert_eq!(dropped.pointers, vec![0, 2, 3]);
+    assert_eq!(dropped.values.iter().map(|value| value.column).collect::<Vec<usize>>(), vec![0, 2, 1]);
+    assert_eq!(dropped.get(0, 2), Some(4.0));
+}
+
+#[test]
+fn test_k_core() {
+    let matrix = make_matrix(&[
+        &[(0, 1.0), (1, 2.0), (2, 3.0)],
+        &[(0, 4.0), (1, 5.0)],
+        &[(0, 6.0), (3, 7.0)],
+        &[(2, 8.0)],
+    ]);
+    // Row 3 goes first, then column 2 is left with one value and goes, then column 3 and row 2.
+    let core = matrix.k_core(4, 2, 2);
+    assert_eq!(core.rows, vec![0, 1]);
+    assert_eq!(core.columns, vec![0, 1]);
+    assert_eq!(core.matrix.len(), 4);
+    assert_eq!(core.matrix.get(1, 1), Some(5.0));
+
+    let core = matrix.k_core(5, 0, 0);
+    assert_eq!(core.rows, vec![0, 1, 2, 3]);
+    assert_eq!(core.columns, vec![0, 1, 2, 3, 4]);
+    assert!(matrix.k_core(4, 3, 3).matrix.row_count() == 0);
+}
+
+#[test]
+fn test_csr_csc() {
+    let mut matrix = Csr::new();
+    matrix.start();
+    matrix.next(0, 1.0);
+    matrix.next(2, 2.0);
+    matrix.start();
+    matrix.next(2, 3.0);
+    matrix.start();
+
+    let matrix = CsrCsc::new(matrix, 4, AccountIndex::from_ids(vec![100, 200]));
+
+    assert_eq!(matrix.row_count(), 2);
+    assert_eq!(matrix.column_count(), 4);
+    assert_eq!(matrix.get_row(1)[0].column, 2);
+    assert_eq!(matrix.get_column(2).iter().map(|value| (value.column, value.value)).collect::<Vec<(usize, f64)>>(), vec![(0, 2.0), (1, 3.0)]);
+    assert!(matrix.get_column(3).is_empty());
+    // The CSC view maps its columns into accounts through the same index.
+    assert_eq!(matrix.accounts().get_account_id(matrix.get_column(2)[1].column), 200);
+    let (matrix, accounts) = matrix.into_csr();
+    assert_eq!(matrix.len(), 3);
+    assert_eq!(accounts.find_row(100), Some(0));
+}
@@ -1,19 +1,149 @@
 //! Tankopedia.
 
 use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::path::Path;
+use std::u16;
 
+use rustc_serialize::json::Json;
+
+/// Highest tank tier.
+pub const MAX_TIER: u8 = 10;
+
+/// Vehicle class.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Class {
+    Light,
+    Medium,
+    Heavy,
+    TankDestroyer,
+    Artillery,
+}
+
+impl Class {
+    /// Parses vehicle class as in Wargaming API or its short name.
+    pub fn parse(value: &str) -> Option<Class> {
+        match &value.to_lowercase()[..] {
+            "lighttank" | "light" | "lt" => Some(Class::Light),
+            "mediumtank" | "medium" | "mt" => Some(Class::Medium),
+            "heavytank" | "heavy" | "ht" => Some(Class::Heavy),
+            "at-spg" | "td" | "tank_destroyer" => Some(Class::TankDestroyer),
+            "spg" | "arty" | "artillery" => Some(Class::Artillery),
+            _ => None,
+        }
+    }
+}
+
+/// Tank metadata.
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct Tank {
+    pub id: u16,
+    pub name: String,
+    pub nation: String,
+    pub tier: u8,
+    pub class: Class,
+    pub is_premium: bool,
+}
+
+/// Tankopedia loading error.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// Malformed tankopedia file.
+    Parse(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref error) => write!(f, "I/O error: {}", error),
+            Error::Parse(ref message) => write!(f, "parse error: {}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(_) => "I/O error",
+            Error::Parse(_) => "parse error",
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[derive(Clone)]
 pub struct Encyclopedia {
     /// Maps tank ID into column number.
-    tank_column: HashMap<u16, usize>
+    tank_column: HashMap<u16, usize>,
+    /// Maps column number into tank ID.
+    column_tank: Vec<u16>,
+    /// Maps tank ID into metadata. Empty for the built-in table.
+    tanks: HashMap<u16, Tank>,
+    /// Maps tank ID into IDs of the tanks researched from it.
+    next_tanks: HashMap<u16, Vec<u16>>,
 }
 
 impl Encyclopedia {
+    /// Loads an encyclopedia from a tankopedia JSON or TSV export.
+    ///
+    /// Columns are assigned in the tank ID order.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = try!(read_text(path));
+        let tanks = try!(if is_json(&text) { parse_json(&text) } else { parse_tsv(&text) });
+        Encyclopedia::from_tanks(tanks)
+    }
+
+    /// Loads tech tree research links from JSON or CSV/TSV file.
+    pub fn load_tech_tree(&mut self, path: &Path) -> Result<(), Error> {
+        let text = try!(read_text(path));
+        let links = try!(if is_json(&text) { parse_tech_tree_json(&text) } else { parse_tech_tree_csv(&text) });
+        for (parent_id, child_id) in links {
+            self.add_research(parent_id, child_id);
+        }
+        Ok(())
+    }
+
+    /// Makes an encyclopedia from tank metadata.
+    ///
+    /// Columns are assigned in the tank ID order. Fails on a duplicate tank ID.
+    pub fn from_tanks(mut tanks: Vec<Tank>) -> Result<Self, Error> {
+        tanks.sort_by(|a, b| a.id.cmp(&b.id));
+        if let Some(pair) = tanks.windows(2).find(|pair| pair[0].id == pair[1].id) {
+            return Err(Error::Parse(format!("duplicate tank ID {}", pair[0].id)));
+        }
+        Ok(Encyclopedia {
+            tank_column: tanks.iter().enumerate().map(|(column, tank)| (tank.id, column)).collect(),
+            column_tank: tanks.iter().map(|tank| tank.id).collect(),
+            tanks: tanks.into_iter().map(|tank| (tank.id, tank)).collect(),
+            next_tanks: HashMap::new(),
+        })
+    }
+
     /// Gets tank count.
     pub fn len(&self) -> usize {
         self.tank_column.len()
     }
 
+    /// Gets whether the tank ID is known.
+    pub fn contains(&self, tank_id: u16) -> bool {
+        self.tank_column.contains_key(&tank_id)
+    }
+
     /// Gets column by tank ID.
+    ///
+    /// Panics if the tank is unknown.
+    #[allow(dead_code)]
     pub fn get_column(&self, tank_id: u16) -> usize {
         match self.tank_column.get(&tank_id) {
             Some(&column) => column,
@@ -21,6 +151,71 @@ impl Encyclopedia {
         }
     }
 
+    /// Gets column by tank ID if the tank is known.
+    pub fn find_column(&self, tank_id: u16) -> Option<usize> {
+        self.tank_column.get(&tank_id).cloned()
+    }
+
+    /// Adds tank ID assigning it the next column.
+    ///
+    /// Returns the existing column if the tank is already known.
+    pub fn add(&mut self, tank_id: u16) -> usize {
+        if let Some(&column) = self.tank_column.get(&tank_id) {
+            return column;
+        }
+        let column = self.column_tank.len();
+        self.tank_column.insert(tank_id, column);
+        self.column_tank.push(tank_id);
+        column
+    }
+
+    /// Gets tank ID by column.
+    pub fn get_tank_id(&self, column: usize) -> u16 {
+        self.column_tank[column]
+    }
+
+    /// Gets tank metadata if it is loaded.
+    #[allow(dead_code)]
+    pub fn get_tank(&self, tank_id: u16) -> Option<&Tank> {
+        self.tanks.get(&tank_id)
+    }
+
+    /// Adds tech tree link: the child tank is researched from the parent one.
+    pub fn add_research(&mut self, parent_id: u16, child_id: u16) {
+        let next_tanks = self.next_tanks.entry(parent_id).or_insert_with(Vec::new);
+        if !next_tanks.contains(&child_id) {
+            next_tanks.push(child_id);
+        }
+    }
+
+    /// Gets IDs of the tanks researched from the tank.
+    pub fn get_next_tanks(&self, tank_id: u16) -> &[u16] {
+        self.next_tanks.get(&tank_id).map_or(&[], |next_tanks| &next_tanks[..])
+    }
+
+    /// Gets IDs of the tanks that can be researched next from the owned ones.
+    ///
+    /// Returns sorted IDs of the tanks that are not owned yet.
+    pub fn get_researchable(&self, owned: &[u16]) -> Vec<u16> {
+        let mut researchable: Vec<u16> = owned.iter()
+            .flat_map(|&tank_id| self.get_next_tanks(tank_id).iter().cloned())
+            .filter(|tank_id| !owned.contains(tank_id))
+            .collect();
+        researchable.sort();
+        researchable.dedup();
+        researchable
+    }
+
+    /// Gets human-readable tank name followed by its ID, e.g. `T-34 #1`.
+    ///
+    /// Falls back to `#ID` if there is no metadata.
+    pub fn get_name(&self, tank_id: u16) -> String {
+        match self.tanks.get(&tank_id) {
+            Some(tank) => format!("{} #{}", tank.name, tank_id),
+            None => format!("#{}", tank_id),
+        }
+    }
+
     /// Initializes an encyclopedia instance.
     pub fn new() -> Self {
         let mut tank_column = HashMap::new();
@@ -422,6 +617,288 @@ impl Encyclopedia {
         tank_column.insert(59137, 394);
         tank_column.insert(59649, 395);
 
-        Encyclopedia { tank_column: tank_column }
+        let mut column_tank = vec![0; tank_column.len()];
+        for (&tank_id, &column) in &tank_column {
+            column_tank[column] = tank_id;
+        }
+
+        Encyclopedia { tank_column: tank_column, column_tank: column_tank, tanks: HashMap::new(), next_tanks: HashMap::new() }
     }
 }
+
+/// Reads the whole file.
+fn read_text(path: &Path) -> Result<String, Error> {
+    let mut text = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut text));
+    Ok(text)
+}
+
+/// Gets whether the file contents look like JSON.
+fn is_json(text: &str) -> bool {
+    text.trim_start().starts_with(|c| c == '{' || c == '[')
+}
+
+/// Parses tankopedia JSON.
+///
+/// Accepts either Wargaming API response with the `data` object, an object of tanks or an array of tanks.
+pub fn parse_json(text: &str) -> Result<Vec<Tank>, Error> {
+    let json = try!(Json::from_str(text).map_err(|error| Error::Parse(error.to_string())));
+    let json = json.find("data").unwrap_or(&json);
+    let items: Vec<&Json> = match *json {
+        Json::Object(ref object) => object.values().collect(),
+        Json::Array(ref array) => array.iter().collect(),
+        _ => return Err(Error::Parse("expected object or array of tanks".to_string())),
+    };
+    items.into_iter().map(parse_json_tank).collect()
+}
+
+/// Parses tank metadata JSON object.
+fn parse_json_tank(json: &Json) -> Result<Tank, Error> {
+    let id = try!(find_json(json, &["tank_id", "id"]).and_then(Json::as_u64).ok_or(parse_error("tank ID", json)));
+    if id > u16::MAX as u64 {
+        return Err(Error::Parse(format!("tank ID {} is out of range", id)));
+    }
+    Ok(Tank {
+        id: id as u16,
+        name: try!(find_json(json, &["name", "short_name"]).and_then(Json::as_string).ok_or(parse_error("name", json))).to_string(),
+        nation: try!(find_json(json, &["nation"]).and_then(Json::as_string).ok_or(parse_error("nation", json))).to_string(),
+        tier: try!(find_json(json, &["tier", "level"]).and_then(Json::as_u64).and_then(to_tier).ok_or(parse_error("tier", json))),
+        class: try!(find_json(json, &["type", "class"]).and_then(Json::as_string).and_then(Class::parse).ok_or(parse_error("class", json))),
+        is_premium: find_json(json, &["is_premium", "premium"]).and_then(Json::as_boolean).unwrap_or(false),
+    })
+}
+
+/// Finds the first present key of the object.
+fn find_json<'a>(json: &'a Json, keys: &[&str]) -> Option<&'a Json> {
+    keys.iter().filter_map(|key| json.find(key)).next()
+}
+
+/// Checks that the tier is from 1 to `MAX_TIER`.
+fn to_tier(tier: u64) -> Option<u8> {
+    if tier >= 1 && tier <= MAX_TIER as u64 { Some(tier as u8) } else { None }
+}
+
+fn parse_error(field: &str, json: &Json) -> Error {
+    Error::Parse(format!("missing or invalid {} in {}", field, json))
+}
+
+/// Parses tankopedia TSV.
+///
+/// Columns are tank ID, name, nation, tier, class and premium flag.
+/// Blank and `#` comment lines are skipped. The first other line may be a header.
+pub fn parse_tsv(text: &str) -> Result<Vec<Tank>, Error> {
+    let mut tanks = Vec::new();
+    let mut is_first = true;
+
+    for (index, line) in text.lines().enumerate() {
+        if is_blank_or_comment(line) {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').map(|field| field.trim()).collect();
+        if mem::replace(&mut is_first, false) && fields[0].parse::<u16>().is_err() {
+            continue; // header
+        }
+        if fields.len() != 6 {
+            return Err(Error::Parse(format!("line {}: expected 6 fields, found {}", index + 1, fields.len())));
+        }
+        let error = |field: &str| Error::Parse(format!("line {}: invalid {}", index + 1, field));
+        tanks.push(Tank {
+            id: try!(fields[0].parse().map_err(|_| error("tank ID"))),
+            name: fields[1].to_string(),
+            nation: fields[2].to_string(),
+            tier: try!(fields[3].parse().ok().and_then(to_tier).ok_or(error("tier"))),
+            class: try!(Class::parse(fields[4]).ok_or(error("class"))),
+            is_premium: match fields[5] {
+                "1" | "true" => true,
+                "0" | "false" | "" => false,
+                _ => return Err(error("premium flag")),
+            },
+        });
+    }
+
+    Ok(tanks)
+}
+
+/// Parses tech tree JSON.
+///
+/// Accepts the same layouts as `parse_json`. Each tank has `next_tanks` either as
+/// Wargaming API object keyed by tank ID or as an array of tank IDs.
+#[allow(dead_code)]
+pub fn parse_tech_tree_json(text: &str) -> Result<Vec<(u16, u16)>, Error> {
+    let json = try!(Json::from_str(text).map_err(|error| Error::Parse(error.to_string())));
+    let json = json.find("data").unwrap_or(&json);
+    let items: Vec<&Json> = match *json {
+        Json::Object(ref object) => object.values().collect(),
+        Json::Array(ref array) => array.iter().collect(),
+        _ => return Err(Error::Parse("expected object or array of tanks".to_string())),
+    };
+    let mut links = Vec::new();
+
+    for item in items {
+        let parent_id = try!(find_json(item, &["tank_id", "id"]).and_then(Json::as_u64).ok_or(parse_error("tank ID", item)));
+        let child_ids: Vec<u64> = match item.find("next_tanks") {
+            None | Some(&Json::Null) => Vec::new(),
+            Some(&Json::Object(ref object)) => try!(object.keys().map(|key| key.parse().map_err(|_| parse_error("next tank ID", item))).collect()),
+            Some(&Json::Array(ref array)) => try!(array.iter().map(|id| id.as_u64().ok_or(parse_error("next tank ID", item))).collect()),
+            Some(_) => return Err(parse_error("next tanks", item)),
+        };
+        for child_id in child_ids {
+            if parent_id > u16::MAX as u64 || child_id > u16::MAX as u64 {
+                return Err(Error::Parse(format!("tank ID {} or {} is out of range", parent_id, child_id)));
+            }
+            links.push((parent_id as u16, child_id as u16));
+        }
+    }
+
+    Ok(links)
+}
+
+/// Parses tech tree CSV or TSV.
+///
+/// Each line contains parent and child tank IDs.
+/// Blank and `#` comment lines are skipped. The first other line may be a header.
+#[allow(dead_code)]
+pub fn parse_tech_tree_csv(text: &str) -> Result<Vec<(u16, u16)>, Error> {
+    let mut links = Vec::new();
+    let mut is_first = true;
+
+    for (index, line) in text.lines().enumerate() {
+        if is_blank_or_comment(line) {
+            continue;
+        }
+        let line = line.trim();
+        let separator = if line.contains('\t') { '\t' } else { ',' };
+        let fields: Vec<&str> = line.split(separator).map(|field| field.trim()).collect();
+        if mem::replace(&mut is_first, false) && fields[0].parse::<u16>().is_err() {
+            continue; // header
+        }
+        if fields.len() != 2 {
+            return Err(Error::Parse(format!("line {}: expected 2 fields, found {}", index + 1, fields.len())));
+        }
+        let error = |_| Error::Parse(format!("line {}: invalid tank ID", index + 1));
+        links.push((try!(fields[0].parse().map_err(&error)), try!(fields[1].parse().map_err(&error))));
+    }
+
+    Ok(links)
+}
+
+/// Gets whether the line is blank or a `#` comment.
+fn is_blank_or_comment(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || line.starts_with('#')
+}
+
+#[test]
+fn test_add() {
+    let mut encyclopedia = Encyclopedia::new();
+    let length = encyclopedia.len();
+    assert_eq!(encyclopedia.find_column(65535), None);
+    assert_eq!(encyclopedia.add(65535), length);
+    assert_eq!(encyclopedia.add(65534), length + 1);
+    assert_eq!(encyclopedia.add(65535), length);
+    assert_eq!(encyclopedia.find_column(65535), Some(length));
+    assert_eq!(encyclopedia.add(1), 0);
+    assert_eq!(encyclopedia.len(), length + 2);
+    assert_eq!(encyclopedia.get_tank_id(length + 1), 65534);
+}
+
+#[test]
+fn test_get_tank_id() {
+    let encyclopedia = Encyclopedia::new();
+    for column in 0..encyclopedia.len() {
+        assert_eq!(encyclopedia.get_column(encyclopedia.get_tank_id(column)), column);
+    }
+    assert_eq!(encyclopedia.get_tank_id(0), 1);
+    assert_eq!(encyclopedia.get_name(1), "#1");
+}
+
+#[test]
+fn test_parse_json() {
+    let tanks = parse_json(r#"{"status": "ok", "data": {
+        "1": {"tank_id": 1, "name": "T-34", "nation": "ussr", "tier": 5, "type": "mediumTank", "is_premium": false},
+        "33": {"tank_id": 33, "name": "T14", "nation": "usa", "level": 5, "type": "heavyTank", "is_premium": true}
+    }}"#).unwrap();
+    assert_eq!(tanks.len(), 2);
+    assert_eq!(tanks[0].id, 1);
+    assert_eq!(tanks[0].name, "T-34");
+    assert_eq!(tanks[0].class, Class::Medium);
+    assert!(!tanks[0].is_premium);
+    assert_eq!(tanks[1].tier, 5);
+    assert_eq!(tanks[1].class, Class::Heavy);
+    assert!(tanks[1].is_premium);
+
+    let tanks = parse_json(r#"[{"id": 49, "name": "Type 59", "nation": "china", "tier": 8, "class": "MT"}]"#).unwrap();
+    assert_eq!(tanks[0].id, 49);
+    assert!(!tanks[0].is_premium);
+
+    assert!(parse_json(r#"[{"id": 49, "name": "Type 59"}]"#).is_err());
+    assert!(parse_json(r#"[{"id": 49, "name": "Type 59", "nation": "china", "tier": 264, "class": "MT"}]"#).is_err());
+}
+
+#[test]
+fn test_parse_tsv() {
+    let tanks = parse_tsv("tank_id\tname\tnation\ttier\tclass\tpremium\n81\tMatilda\tuk\t4\tmediumTank\t0\n\n273\tHummel\tgermany\t6\tSPG\t1\n").unwrap();
+    assert_eq!(tanks.len(), 2);
+    assert_eq!(tanks[0].id, 81);
+    assert_eq!(tanks[0].nation, "uk");
+    assert_eq!(tanks[1].class, Class::Artillery);
+    assert!(tanks[1].is_premium);
+
+    assert!(parse_tsv("1\tT-34\tussr\tfive\tmediumTank\t0").is_err());
+    assert!(parse_tsv("1\tT-34\tussr\t0\tmediumTank\t0").is_err());
+    assert!(parse_tsv("1\tT-34\tussr\t11\tmediumTank\t0").is_err());
+
+    // Header after blank and comment lines.
+    let tanks = parse_tsv("\n# Exported tankopedia.\ntank_id\tname\tnation\ttier\tclass\tpremium\n81\tMatilda\tuk\t4\tmediumTank\t0\n").unwrap();
+    assert_eq!(tanks.len(), 1);
+    assert_eq!(tanks[0].id, 81);
+    // Only the first line may be a header.
+    assert!(parse_tsv("81\tMatilda\tuk\t4\tmediumTank\t0\ntank_id\tname\tnation\ttier\tclass\tpremium\n").is_err());
+}
+
+#[test]
+fn test_from_tanks() {
+    let tank = |id| Tank { id: id, name: String::new(), nation: String::new(), tier: 1, class: Class::Light, is_premium: false };
+    let encyclopedia = Encyclopedia::from_tanks(vec![tank(300), tank(1), tank(33)]).unwrap();
+    assert_eq!(encyclopedia.len(), 3);
+    assert_eq!(encyclopedia.get_column(1), 0);
+    assert_eq!(encyclopedia.get_column(33), 1);
+    assert_eq!(encyclopedia.get_column(300), 2);
+    assert_eq!(encyclopedia.get_tank_id(1), 33);
+    assert_eq!(encyclopedia.get_tank(33).map(|tank| tank.id), Some(33));
+    assert!(encyclopedia.get_tank(2).is_none());
+
+    match Encyclopedia::from_tanks(vec![tank(300), tank(1), tank(33), tank(1)]) {
+        Err(Error::Parse(ref message)) if message == "duplicate tank ID 1" => (),
+        Err(error) => panic!("{}", error),
+        Ok(_) => panic!(),
+    }
+}
+
+#[test]
+fn test_parse_tech_tree() {
+    let links = parse_tech_tree_json(r#"{"data": {"1": {"tank_id": 1, "next_tanks": {"33": 1500, "49": 2000}}, "33": {"tank_id": 33, "next_tanks": null}}}"#).unwrap();
+    assert_eq!(links.len(), 2);
+    assert!(links.contains(&(1, 33)));
+    assert!(links.contains(&(1, 49)));
+    assert_eq!(parse_tech_tree_json(r#"[{"id": 81, "next_tanks": [1]}]"#).unwrap(), vec![(81, 1)]);
+
+    assert_eq!(parse_tech_tree_csv("parent,child\n1,33\n\n33\t49\n").unwrap(), vec![(1, 33), (33, 49)]);
+    assert_eq!(parse_tech_tree_csv("\n# Research links.\nparent,child\n1,33\n").unwrap(), vec![(1, 33)]);
+    assert!(parse_tech_tree_csv("1,x").is_err());
+}
+
+#[test]
+fn test_get_researchable() {
+    let mut encyclopedia = Encyclopedia::new();
+    encyclopedia.add_research(1, 33);
+    encyclopedia.add_research(1, 49);
+    encyclopedia.add_research(1, 33);
+    encyclopedia.add_research(33, 81);
+    encyclopedia.add_research(49, 81);
+    assert_eq!(encyclopedia.get_next_tanks(1), &[33, 49]);
+    assert!(encyclopedia.get_next_tanks(81).is_empty());
+    assert_eq!(encyclopedia.get_researchable(&[1]), vec![33, 49]);
+    assert_eq!(encyclopedia.get_researchable(&[1, 33]), vec![49, 81]);
+    assert!(encyclopedia.get_researchable(&[81]).is_empty());
+}
@@ -0,0 +1,315 @@
+//! Model evaluation metrics.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use rand::{Rng, thread_rng};
+
+use csr;
+use encyclopedia::Encyclopedia;
+use helpers;
+
+/// Accumulated prediction errors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metrics {
+    /// Prediction count.
+    pub count: usize,
+    /// Sum of signed errors, i.e. predicted minus actual values.
+    error_sum: f64,
+    absolute_error_sum: f64,
+    squared_error_sum: f64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics { count: 0, error_sum: 0.0, absolute_error_sum: 0.0, squared_error_sum: 0.0 }
+    }
+
+    /// Adds prediction error.
+    pub fn add(&mut self, predicted_value: f64, actual_value: f64) {
+        let error = predicted_value - actual_value;
+        self.count += 1;
+        self.error_sum += error;
+        self.absolute_error_sum += error.abs();
+        self.squared_error_sum += error * error;
+    }
+
+    /// Adds errors of the other metrics.
+    pub fn merge(&mut self, other: &Metrics) {
+        self.count += other.count;
+        self.error_sum += other.error_sum;
+        self.absolute_error_sum += other.absolute_error_sum;
+        self.squared_error_sum += other.squared_error_sum;
+    }
+
+    /// Gets mean absolute error. `NaN` if there are no predictions.
+    pub fn mae(&self) -> f64 {
+        self.absolute_error_sum / self.count as f64
+    }
+
+    /// Gets root mean squared error. `NaN` if there are no predictions.
+    pub fn rmse(&self) -> f64 {
+        (self.squared_error_sum / self.count as f64).sqrt()
+    }
+
+    /// Gets mean signed error. Positive if the model overestimates. `NaN` if there are no predictions.
+    pub fn bias(&self) -> f64 {
+        self.error_sum / self.count as f64
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MAE: {:.6} | RMSE: {:.6} | bias: {:+.6} | count: {}", self.mae(), self.rmse(), self.bias(), self.count)
+    }
+}
+
+/// Evaluation report.
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// Metrics of all predictions.
+    pub total: Metrics,
+    /// Metrics of each column.
+    pub columns: Vec<Metrics>,
+    /// Number of test values the model could not predict.
+    pub unpredicted_count: usize,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Report { total: Metrics::new(), columns: Vec::new(), unpredicted_count: 0 }
+    }
+
+    /// Adds prediction of the value in the column.
+    pub fn add(&mut self, column: usize, predicted_value: f64, actual_value: f64) {
+        if self.columns.len() <= column {
+            self.columns.resize(column + 1, Metrics::new());
+        }
+        self.total.add(predicted_value, actual_value);
+        self.columns[column].add(predicted_value, actual_value);
+    }
+
+    /// Adds the value the model could not predict.
+    pub fn add_unpredicted(&mut self) {
+        self.unpredicted_count += 1;
+    }
+
+    /// Gets metrics of each tier. Tanks without metadata fall into `None`.
+    pub fn get_tiers(&self, encyclopedia: &Encyclopedia) -> BTreeMap<Option<u8>, Metrics> {
+        let mut tiers = BTreeMap::new();
+        for (column, metrics) in self.columns.iter().enumerate().filter(|&(_, metrics)| metrics.count != 0) {
+            let tier = encyclopedia.get_tank(encyclopedia.get_tank_id(column)).map(|tank| tank.tier);
+            tiers.entry(tier).or_insert(Metrics::new()).merge(metrics);
+        }
+        tiers
+    }
+
+    /// Prints per-tank metrics starting from the worst MAE.
+    pub fn print_columns(&self, encyclopedia: &Encyclopedia) {
+        let mut columns: Vec<(usize, &Metrics)> = self.columns.iter().enumerate().filter(|&(_, metrics)| metrics.count != 0).collect();
+        columns.sort_by(|&(_, a), &(_, b)| b.mae().partial_cmp(&a.mae()).unwrap());
+
+        for (column, metrics) in columns {
+            println!("  {0:>36}: {1}", encyclopedia.get_name(encyclopedia.get_tank_id(column)), metrics);
+        }
+    }
+
+    /// Prints per-tier metrics.
+    pub fn print_tiers(&self, encyclopedia: &Encyclopedia) {
+        for (tier, metrics) in self.get_tiers(encyclopedia) {
+            match tier {
+                Some(tier) => println!("  {0:>7}: {1}", tier, metrics),
+                None => println!("  {0:>7}: {1}", "unknown", metrics),
+            }
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} | unpredicted: {}", self.total, self.unpredicted_count)
+    }
+}
+
+/// Cross-validation result.
+pub struct CrossValidation {
+    /// Test reports of the folds.
+    pub reports: Vec<Report>,
+}
+
+impl CrossValidation {
+    /// Gets mean and sample standard deviation of the metric over the folds.
+    pub fn get_statistics<F: Fn(&Metrics) -> f64>(&self, f: F) -> (f64, f64) {
+        let values: Vec<f64> = self.reports.iter().map(|report| f(&report.total)).collect();
+        let count = values.len() as f64;
+        let mean = values.iter().fold(0.0, |sum, value| sum + value) / count;
+        let variance = values.iter().fold(0.0, |sum, value| sum + (value - mean).powi(2)) / (count - 1.0);
+        (mean, variance.sqrt())
+    }
+
+    /// Prints mean and standard deviation of each metric.
+    pub fn print(&self) {
+        let metrics: [(&str, &Fn(&Metrics) -> f64); 4] = [
+            ("MAE", &|metrics: &Metrics| metrics.mae()),
+            ("RMSE", &|metrics: &Metrics| metrics.rmse()),
+            ("bias", &|metrics: &Metrics| metrics.bias()),
+            ("count", &|metrics: &Metrics| metrics.count as f64),
+        ];
+        for &(name, f) in &metrics {
+            let (mean, deviation) = self.get_statistics(f);
+            println!("  {0:>5}: {1:.6} ± {2:.6}", name, mean, deviation);
+        }
+    }
+}
+
+/// Randomly assigns each value of the matrix to one of the folds.
+///
+/// Fold sizes differ by one at most.
+pub fn assign_folds<C: csr::Index, V: csr::Value>(matrix: &csr::Csr<C, V>, fold_count: usize) -> Vec<usize> {
+    let mut folds: Vec<usize> = (0..matrix.len()).map(|index| index % fold_count).collect();
+    thread_rng().shuffle(&mut folds);
+    folds
+}
+
+/// Splits the matrix into train and test matrices.
+///
+/// Values assigned to the fold make the test matrix, the others make the train matrix. Rows are kept.
+pub fn split_fold<C: csr::Index, V: csr::Value>(matrix: &csr::Csr<C, V>, folds: &[usize], fold: usize) -> (csr::Csr<C, V>, csr::Csr<C, V>) {
+    let mut train_matrix = csr::CsrBuilder::default();
+    let mut test_matrix = csr::CsrBuilder::default();
+    let mut folds = folds.iter();
+
+    for row_index in 0..matrix.row_count() {
+        for value in matrix.get_row(row_index) {
+            (if *folds.next().unwrap() != fold {
+                &mut train_matrix
+            } else {
+                &mut test_matrix
+            }).next(value.column, value.value);
+        }
+        train_matrix.end_row();
+        test_matrix.end_row();
+    }
+
+    (train_matrix.build(), test_matrix.build())
+}
+
+/// Performs k-fold cross-validation over the matrix values.
+///
+/// `train` makes a model trained on the given matrix. Each fold is evaluated once as the test matrix.
+pub fn cross_validate<C, V, M, T, F>(matrix: &csr::Csr<C, V>, fold_count: usize, train: T, inverse_f: F) -> CrossValidation
+    where C: csr::Index, V: csr::Value, M: helpers::AbstractModel<C, V>, T: Fn(&csr::Csr<C, V>) -> M, F: Fn(f64) -> f64 {
+
+    assert!(fold_count >= 2, "at least 2 folds are needed: {}", fold_count);
+
+    let folds = assign_folds(matrix, fold_count);
+    let mut reports = Vec::new();
+
+    for fold in 0..fold_count {
+        let (train_matrix, test_matrix) = split_fold(matrix, &folds, fold);
+        let model = train(&train_matrix);
+        let report = helpers::evaluate(&model, &train_matrix, &test_matrix, &inverse_f);
+        println!("Fold #{}: {}.", fold + 1, report);
+        reports.push(report);
+    }
+
+    CrossValidation { reports: reports }
+}
+
+#[test]
+fn test_metrics() {
+    let mut metrics = Metrics::new();
+    metrics.add(3.0, 1.0);
+    metrics.add(1.0, 2.0);
+    assert_eq!(metrics.count, 2);
+    assert_eq!(metrics.mae(), 1.5);
+    assert_eq!(metrics.rmse(), 2.5f64.sqrt());
+    assert_eq!(metrics.bias(), 0.5);
+
+    let mut other = Metrics::new();
+    other.add(0.0, 0.0);
+    other.merge(&metrics);
+    assert_eq!(other.count, 3);
+    assert_eq!(other.mae(), 1.0);
+    assert!(Metrics::new().mae().is_nan());
+}
+
+#[test]
+fn test_report() {
+    use encyclopedia::{Class, Tank};
+
+    let make_tank = |id: u16, tier: u8| Tank { id: id, name: format!("Tank {}", id), nation: "ussr".to_string(), tier: tier, class: Class::Heavy, is_premium: false };
+    let mut encyclopedia = Encyclopedia::from_tanks(vec![make_tank(1, 5), make_tank(2, 5), make_tank(3, 8)]).unwrap();
+    let column = encyclopedia.add(4);
+
+    let mut report = Report::new();
+    report.add(0, 50.0, 40.0);
+    report.add(1, 50.0, 60.0);
+    report.add(2, 55.0, 50.0);
+    report.add(column, 45.0, 50.0);
+    report.add_unpredicted();
+
+    assert_eq!(report.total.count, 4);
+    assert_eq!(report.total.bias(), 0.0);
+    assert_eq!(report.columns.len(), 4);
+    assert_eq!(report.columns[2].mae(), 5.0);
+    assert_eq!(report.unpredicted_count, 1);
+
+    let tiers = report.get_tiers(&encyclopedia);
+    assert_eq!(tiers.keys().cloned().collect::<Vec<Option<u8>>>(), vec![None, Some(5), Some(8)]);
+    assert_eq!(tiers[&Some(5)].count, 2);
+    assert_eq!(tiers[&Some(5)].bias(), 0.0);
+    assert_eq!(tiers[&Some(5)].mae(), 10.0);
+    assert_eq!(tiers[&None].count, 1);
+}
+
+#[test]
+fn test_split_fold() {
+    let mut matrix = csr::Csr::new();
+    matrix.start();
+    for column in 0..10 {
+        matrix.next(column, column as f64);
+    }
+    matrix.start();
+    matrix.next(0, 10.0);
+    matrix.start();
+
+    let folds = assign_folds(&matrix, 3);
+    assert_eq!((0..3).map(|fold| folds.iter().filter(|&&other| other == fold).count()).collect::<Vec<usize>>(), vec![4, 4, 3]);
+
+    let (train_matrix, test_matrix) = split_fold(&matrix, &folds, 0);
+    assert_eq!(train_matrix.row_count(), 2);
+    assert_eq!(test_matrix.row_count(), 2);
+    assert_eq!(train_matrix.len(), 7);
+    assert_eq!(test_matrix.len(), 4);
+    assert_eq!(train_matrix.merge_rows(&test_matrix).len(), matrix.len());
+}
+
+#[test]
+fn test_cross_validate() {
+    struct MeanModel {
+        mean: f64,
+    }
+
+    impl helpers::AbstractModel for MeanModel {
+        fn predict(&self, _: &csr::Csr, _: usize, _: usize) -> Option<f64> {
+            Some(self.mean)
+        }
+    }
+
+    let mut matrix = csr::Csr::new();
+    for row_index in 0..20 {
+        matrix.start();
+        matrix.next(0, (row_index % 2) as f64);
+    }
+    matrix.start();
+
+    let result = cross_validate(&matrix, 4, |train_matrix| {
+        let mean = train_matrix.column_means(1)[0].unwrap();
+        MeanModel { mean: mean }
+    }, helpers::identity);
+    assert_eq!(result.reports.len(), 4);
+    assert_eq!(result.get_statistics(|metrics| metrics.count as f64), (5.0, 0.0));
+    let (mae, deviation) = result.get_statistics(|metrics| metrics.mae());
+    assert!(mae > 0.3 && mae < 0.7 && deviation >= 0.0);
+}
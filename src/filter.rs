@@ -0,0 +1,244 @@
+//! Tank filters for predictions and recommendations.
+//!
+//! Query is a whitespace-separated list of `key=value` terms, e.g. `tier=6-8 class=heavy,td nation=ussr premium=no`.
+
+use csr;
+use encyclopedia::{Class, Encyclopedia, Tank};
+use helpers::AbstractModel;
+
+/// Tank filter. Empty filter matches every tank.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    pub min_tier: Option<u8>,
+    pub max_tier: Option<u8>,
+    /// Allowed nations. Any nation if empty.
+    pub nations: Vec<String>,
+    /// Allowed classes. Any class if empty.
+    pub classes: Vec<Class>,
+    pub is_premium: Option<bool>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Filter { min_tier: None, max_tier: None, nations: Vec::new(), classes: Vec::new(), is_premium: None }
+    }
+
+    /// Parses filter query.
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut filter = Filter::new();
+
+        for term in query.split_whitespace() {
+            let mut parts = term.splitn(2, '=');
+            let key = parts.next().unwrap();
+            let value = try!(parts.next().ok_or(format!("expected key=value: {:?}", term)));
+            match key {
+                "tier" => {
+                    let mut tiers = value.splitn(2, '-');
+                    let min_tier = try!(parse_tier(tiers.next().unwrap()));
+                    let max_tier = match tiers.next() {
+                        Some(max_tier) => try!(parse_tier(max_tier)),
+                        None => min_tier,
+                    };
+                    filter.min_tier = Some(min_tier);
+                    filter.max_tier = Some(max_tier);
+                }
+                "nation" => filter.nations = value.split(',').map(|nation| nation.to_lowercase()).collect(),
+                "class" => {
+                    filter.classes = try!(value.split(',').map(
+                        |class| Class::parse(class).ok_or(format!("invalid class: {:?}", class))).collect());
+                }
+                "premium" => filter.is_premium = Some(match value {
+                    "yes" | "true" | "1" => true,
+                    "no" | "false" | "0" => false,
+                    _ => return Err(format!("invalid premium flag: {:?}", value)),
+                }),
+                _ => return Err(format!("unknown filter: {:?}", key)),
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// Gets whether the filter has no conditions.
+    pub fn is_empty(&self) -> bool {
+        self.min_tier.is_none() && self.max_tier.is_none() && self.nations.is_empty() && self.classes.is_empty() && self.is_premium.is_none()
+    }
+
+    /// Gets whether the tank matches the filter.
+    pub fn matches(&self, tank: &Tank) -> bool {
+        self.min_tier.map_or(true, |min_tier| tank.tier >= min_tier) &&
+        self.max_tier.map_or(true, |max_tier| tank.tier <= max_tier) &&
+        (self.nations.is_empty() || self.nations.iter().any(|nation| *nation == tank.nation.to_lowercase())) &&
+        (self.classes.is_empty() || self.classes.contains(&tank.class)) &&
+        self.is_premium.map_or(true, |is_premium| tank.is_premium == is_premium)
+    }
+
+    /// Gets whether the tank in the column matches the filter.
+    ///
+    /// Tanks without metadata match only the empty filter.
+    pub fn matches_column(&self, encyclopedia: &Encyclopedia, column: usize) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        encyclopedia.get_tank(encyclopedia.get_tank_id(column)).map_or(false, |tank| self.matches(tank))
+    }
+
+    /// Gets matching columns.
+    pub fn get_columns(&self, encyclopedia: &Encyclopedia) -> Vec<usize> {
+        (0..encyclopedia.len()).filter(|&column| self.matches_column(encyclopedia, column)).collect()
+    }
+}
+
+/// Parses tier in either Arabic or Roman numerals.
+fn parse_tier(value: &str) -> Result<u8, String> {
+    const ROMAN: [&'static str; 10] = ["I", "II", "III", "IV", "V", "VI", "VII", "VIII", "IX", "X"];
+
+    match ROMAN.iter().position(|&tier| tier == value.to_uppercase()) {
+        Some(index) => Ok(index as u8 + 1),
+        None => value.parse().map_err(|_| format!("invalid tier: {:?}", value)),
+    }
+}
+
+/// Recommends tanks to the account in the row.
+///
+/// Returns up to `count` columns the account does not own yet with the highest predicted rating.
+/// The owned columns include the ones that are not in the train row, e.g. the test ratings.
+/// Non-finite predictions are skipped.
+pub fn recommend<C: csr::Index, V: csr::Value>(model: &AbstractModel<C, V>, train_matrix: &csr::Csr<C, V>, row_index: usize, owned: &[usize], columns: &[usize], count: usize) -> Vec<(usize, f64)> {
+    let mut predictions: Vec<(usize, f64)> = columns.iter()
+        .filter(|column| !owned.contains(column))
+        .filter_map(|&column| model.predict(train_matrix, row_index, column).map(|value| (column, value)))
+        .filter(|&(_, value)| value.is_finite())
+        .collect();
+    predictions.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    predictions.truncate(count);
+    predictions
+}
+
+/// Recommends tanks to research next.
+///
+/// Candidates are the tanks researched from the owned columns. Only the given columns are considered.
+pub fn recommend_research<C: csr::Index, V: csr::Value>(model: &AbstractModel<C, V>, train_matrix: &csr::Csr<C, V>, row_index: usize, encyclopedia: &Encyclopedia, owned: &[usize], columns: &[usize], count: usize) -> Vec<(usize, f64)> {
+    let owned_tanks: Vec<u16> = owned.iter().map(|&column| encyclopedia.get_tank_id(column)).collect();
+    let candidates: Vec<usize> = encyclopedia.get_researchable(&owned_tanks).into_iter()
+        .filter_map(|tank_id| encyclopedia.find_column(tank_id))
+        .filter(|column| columns.contains(column))
+        .collect();
+    recommend(model, train_matrix, row_index, owned, &candidates, count)
+}
+
+#[cfg(test)]
+fn make_tank(id: u16, nation: &str, tier: u8, class: Class, is_premium: bool) -> Tank {
+    Tank { id: id, name: format!("Tank {}", id), nation: nation.to_string(), tier: tier, class: class, is_premium: is_premium }
+}
+
+#[test]
+fn test_parse() {
+    let filter = Filter::parse("tier=VI-8 class=heavy,td nation=USSR premium=no").unwrap();
+    assert_eq!(filter.min_tier, Some(6));
+    assert_eq!(filter.max_tier, Some(8));
+    assert_eq!(filter.classes, vec![Class::Heavy, Class::TankDestroyer]);
+    assert_eq!(filter.nations, vec!["ussr".to_string()]);
+    assert_eq!(filter.is_premium, Some(false));
+
+    assert!(Filter::parse("").unwrap().is_empty());
+    assert!(Filter::parse("tier=eleven").is_err());
+    assert!(Filter::parse("class=boat").is_err());
+    assert!(Filter::parse("color=green").is_err());
+    assert!(Filter::parse("tier").is_err());
+}
+
+#[test]
+fn test_matches() {
+    let filter = Filter::parse("tier=8 class=heavy").unwrap();
+    assert!(filter.matches(&make_tank(1, "ussr", 8, Class::Heavy, false)));
+    assert!(!filter.matches(&make_tank(2, "ussr", 7, Class::Heavy, false)));
+    assert!(!filter.matches(&make_tank(3, "ussr", 8, Class::Medium, false)));
+
+    let filter = Filter::parse("nation=germany,usa premium=yes").unwrap();
+    assert!(filter.matches(&make_tank(4, "usa", 5, Class::Light, true)));
+    assert!(!filter.matches(&make_tank(5, "usa", 5, Class::Light, false)));
+    assert!(!filter.matches(&make_tank(6, "uk", 5, Class::Light, true)));
+}
+
+#[test]
+fn test_recommend_filter() {
+    struct ConstantModel;
+
+    impl AbstractModel for ConstantModel {
+        fn predict(&self, _: &csr::Csr, _: usize, column_index: usize) -> Option<f64> {
+            Some(column_index as f64)
+        }
+    }
+
+    let encyclopedia = Encyclopedia::from_tanks(vec![
+        make_tank(1, "ussr", 8, Class::Heavy, false),
+        make_tank(2, "ussr", 8, Class::Medium, false),
+        make_tank(3, "germany", 8, Class::Heavy, false),
+        make_tank(4, "germany", 8, Class::Heavy, true),
+    ]).unwrap();
+    let filter = Filter::parse("class=heavy premium=no").unwrap();
+    assert_eq!(filter.get_columns(&encyclopedia), vec![0, 2]);
+
+    let mut train_matrix = csr::Csr::new();
+    train_matrix.start();
+    train_matrix.next(0, 50.0);
+    train_matrix.start();
+
+    let model = ConstantModel;
+    assert_eq!(recommend(&model, &train_matrix, 0, &[0], &filter.get_columns(&encyclopedia), 10), vec![(2, 2.0)]);
+
+    let all_columns: Vec<usize> = (0..encyclopedia.len()).collect();
+    assert_eq!(recommend(&model, &train_matrix, 0, &[0], &all_columns, 2), vec![(3, 3.0), (2, 2.0)]);
+    // The test rating of column 3 is owned as well.
+    assert_eq!(recommend(&model, &train_matrix, 0, &[0, 3], &all_columns, 2), vec![(2, 2.0), (1, 1.0)]);
+}
+
+#[test]
+fn test_recommend_non_finite() {
+    struct NanModel;
+
+    impl AbstractModel for NanModel {
+        fn predict(&self, _: &csr::Csr, _: usize, column_index: usize) -> Option<f64> {
+            Some(if column_index == 1 { ::std::f64::NAN } else { column_index as f64 })
+        }
+    }
+
+    let mut train_matrix = csr::Csr::new();
+    train_matrix.start();
+    train_matrix.start();
+
+    assert_eq!(recommend(&NanModel, &train_matrix, 0, &[], &[0, 1, 2], 10), vec![(2, 2.0), (0, 0.0)]);
+}
+
+#[test]
+fn test_recommend_research() {
+    struct ConstantModel;
+
+    impl AbstractModel for ConstantModel {
+        fn predict(&self, _: &csr::Csr, _: usize, column_index: usize) -> Option<f64> {
+            Some(column_index as f64)
+        }
+    }
+
+    let mut encyclopedia = Encyclopedia::from_tanks(vec![
+        make_tank(1, "ussr", 1, Class::Light, false),
+        make_tank(2, "ussr", 2, Class::Light, false),
+        make_tank(3, "ussr", 2, Class::Medium, false),
+        make_tank(4, "ussr", 3, Class::Heavy, false),
+    ]).unwrap();
+    encyclopedia.add_research(1, 2);
+    encyclopedia.add_research(1, 3);
+    encyclopedia.add_research(2, 4);
+
+    let mut train_matrix = csr::Csr::new();
+    train_matrix.start();
+    train_matrix.next(0, 50.0);
+    train_matrix.start();
+
+    let all_columns: Vec<usize> = (0..encyclopedia.len()).collect();
+    assert_eq!(recommend_research(&ConstantModel, &train_matrix, 0, &encyclopedia, &[0], &all_columns, 10), vec![(2, 2.0), (1, 1.0)]);
+    assert_eq!(recommend_research(&ConstantModel, &train_matrix, 0, &encyclopedia, &[0], &[1], 10), vec![(1, 1.0)]);
+    // Owned candidates are not recommended.
+    assert_eq!(recommend_research(&ConstantModel, &train_matrix, 0, &encyclopedia, &[0, 2], &all_columns, 10), vec![(1, 1.0)]);
+}
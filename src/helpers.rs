@@ -1,29 +1,145 @@
 //! Helper functions.
 
 use std;
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 use time;
 
+use accounts;
+use checksum;
 use csr;
 use encyclopedia;
+use evaluation;
+use filter;
+use input;
+use parallel;
+use split;
 use stats;
+use stats::AccountSource;
+use text;
 
 pub const MAX_RATING: f64 = 100.0;
 
-pub trait AbstractModel {
+/// Model that predicts ratings from the train matrix of the given column index and value types.
+pub trait AbstractModel<C: csr::Index = usize, V: csr::Value = f64> {
     /// Predicts value at the specified position.
-    fn predict(&self, train_matrix: &csr::Csr, row_index: usize, column_index: usize) -> Option<f64>;
+    fn predict(&self, train_matrix: &csr::Csr<C, V>, row_index: usize, column_index: usize) -> Option<f64>;
 }
 
-pub fn get_stats<F>(min_battles: u32, f: F) -> (encyclopedia::Encyclopedia, csr::Csr, csr::Csr)
-    where F : Fn(f64) -> f64 {
+/// Defines how to handle corrupt accounts while reading statistics.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadMode {
+    /// Stop at the first error.
+    Strict,
+    /// Skip corrupt accounts and report them.
+    SkipCorrupt,
+}
 
-    let mut input = get_input();
-    let encyclopedia = encyclopedia::Encyclopedia::new();
-    let (train_matrix, test_matrix) = read_stats(&mut input, min_battles, &encyclopedia, f);
-    (encyclopedia, train_matrix, test_matrix)
+/// Defines how to handle tank IDs missing in the encyclopedia.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnknownTankPolicy {
+    /// Skip records of unknown tanks and count them.
+    Skip,
+    /// Assign new columns to unknown tanks growing the encyclopedia.
+    Grow,
+}
+
+/// Defines how statistics are read into the train and test matrices.
+#[derive(Clone, Copy, Debug)]
+struct ReadOptions {
+    /// Tank records with fewer battles are skipped.
+    min_battles: u32,
+    mode: ReadMode,
+    policy: UnknownTankPolicy,
+    strategy: split::Strategy,
+}
+
+impl ReadOptions {
+    /// Gets options passed in the command line.
+    fn from_args(min_battles: u32) -> Self {
+        ReadOptions {
+            min_battles: min_battles,
+            mode: get_read_mode(),
+            policy: get_unknown_tank_policy(),
+            strategy: get_split_strategy(),
+        }
+    }
+}
+
+/// Default number of threads to read uncompressed binary statistics.
+const DEFAULT_THREAD_COUNT: usize = 4;
+
+/// Number of accounts between reading progress reports.
+const PROGRESS_INTERVAL: usize = 100000;
+
+/// Reads statistics into train and test matrices of the requested column index and value types.
+///
+/// Returns the encyclopedia of the columns, the matrices and the account index of their rows.
+/// Matrices are pruned to the k-core if `--k-core=TANKS,ACCOUNTS` is passed.
+pub fn get_stats<C: csr::Index, V: csr::Value, F>(min_battles: u32, f: F) -> (encyclopedia::Encyclopedia, csr::Csr<C, V>, csr::Csr<C, V>, accounts::AccountIndex)
+    where F : Fn(f64) -> f64 + Send + Sync + 'static {
+
+    let mut encyclopedia = get_encyclopedia();
+    let options = ReadOptions::from_args(min_battles);
+    println!("Split strategy: {:?}.", options.strategy);
+    // Cache path and the fingerprint of the matrices.
+    let cache = get_option("cache").map(|cache_path| {
+        let fingerprint = get_fingerprint(&get_input_path(), options, &encyclopedia, &f);
+        (cache_path, fingerprint)
+    });
+    let cached_matrices = cache.as_ref().and_then(|&(ref cache_path, ref fingerprint)| load_cache(cache_path, fingerprint, &mut encyclopedia));
+    let (train_matrix, test_matrix, accounts) = match cached_matrices {
+        Some(matrices) => matrices,
+        None => read_input(options, &mut encyclopedia, f, cache),
+    };
+    match get_k_core() {
+        Some((min_tank_count, min_account_count)) => {
+            let (train_matrix, test_matrix, accounts) = prune(train_matrix, test_matrix, &accounts, encyclopedia.len(), min_tank_count, min_account_count);
+            (encyclopedia, train_matrix, test_matrix, accounts)
+        }
+        None => (encyclopedia, train_matrix, test_matrix, accounts),
+    }
+}
+
+/// Reads train and test matrices from the input file and saves them to the cache if requested.
+fn read_input<C: csr::Index, V: csr::Value, F>(options: ReadOptions, encyclopedia: &mut encyclopedia::Encyclopedia, f: F, cache: Option<(String, String)>) -> (csr::Csr<C, V>, csr::Csr<C, V>, accounts::AccountIndex)
+    where F : Fn(f64) -> f64 + Send + Sync + 'static {
+
+    let path = get_input_path();
+    let mut input = get_input(&path);
+    let counter = input.compressed_counter();
+    let thread_count = get_thread_count();
+    let known_column_count = encyclopedia.len();
+    let is_text = match is_text(&mut input) {
+        Ok(is_text) => is_text,
+        Err(error) => panic!("failed to read statistics: {}", error),
+    };
+    let result = if is_text {
+        println!("Input format: text.");
+        let mut reader = text::TextReader::new(input);
+        if has_flag("--sort") {
+            println!("Sorting rows.");
+            reader.sort().and_then(|mut reader| read_stats(&mut reader, &counter, options, encyclopedia, f))
+        } else {
+            read_stats(&mut reader, &counter, options, encyclopedia, f)
+        }
+    } else if input.compression() == input::Compression::None && thread_count > 1 {
+        drop(input);
+        read_stats_parallel(&path, thread_count, options, encyclopedia, f)
+    } else {
+        read_stats(&mut stats::AccountReader::new(input), &counter, options, encyclopedia, f)
+    };
+    match result {
+        Ok((train_matrix, test_matrix, accounts)) => {
+            if let Some((ref cache_path, ref fingerprint)) = cache {
+                let added_tanks: Vec<u16> = (known_column_count..encyclopedia.len()).map(|column| encyclopedia.get_tank_id(column)).collect();
+                save_cache(cache_path, fingerprint, &train_matrix, &test_matrix, &accounts, &added_tanks);
+            }
+            (train_matrix, test_matrix, accounts)
+        }
+        Err(error) => panic!("failed to read statistics: {}", error),
+    }
 }
 
 /// Identity function. Returns the given value.
@@ -40,28 +156,90 @@ pub fn get_seconds(start_time: time::Tm) -> f32 {
 }
 
 /// Evaluates the model.
-pub fn evaluate<F>(model: &AbstractModel, train_matrix: &csr::Csr, test_matrix: &csr::Csr, inverse_f: F) -> f64
+///
+/// Returns error metrics of the test values in total and per column.
+pub fn evaluate<C: csr::Index, V: csr::Value, F>(model: &AbstractModel<C, V>, train_matrix: &csr::Csr<C, V>, test_matrix: &csr::Csr<C, V>, inverse_f: F) -> evaluation::Report
     where F : Fn(f64) -> f64 {
 
-    let mut error_count = 0;
-    let mut error_sum = 0.0;
+    let mut report = evaluation::Report::new();
 
     for row_index in 0..test_matrix.row_count() {
         for actual_value in test_matrix.get_row(row_index) {
-            if let Some(predicted_value) = model.predict(train_matrix, row_index, actual_value.column) {
-                assert!(!predicted_value.is_nan());
-                error_count += 1;
-                error_sum += (inverse_f(predicted_value) - inverse_f(actual_value.value)).abs();
+            match model.predict(train_matrix, row_index, actual_value.column.to_usize()) {
+                Some(predicted_value) => {
+                    assert!(!predicted_value.is_nan());
+                    report.add(actual_value.column.to_usize(), inverse_f(predicted_value), inverse_f(actual_value.value.to_f64()));
+                }
+                None => report.add_unpredicted(),
             }
         }
     }
 
-    assert!(error_count != 0);
-    error_sum / error_count as f64
+    report
+}
+
+/// Cross-validates the model over the merged train and test matrices if `--folds=K` is passed.
+///
+/// Folds are random regardless of the split strategy, so `--split` is rejected along with `--folds`.
+///
+/// `train` makes a model trained on the given matrix. Returns whether cross-validation was performed.
+#[allow(dead_code)]
+pub fn run_cross_validation<C, V, M, T, F>(train_matrix: &csr::Csr<C, V>, test_matrix: &csr::Csr<C, V>, train: T, inverse_f: F) -> bool
+    where C: csr::Index, V: csr::Value, M: AbstractModel<C, V>, T: Fn(&csr::Csr<C, V>) -> M, F: Fn(f64) -> f64 {
+
+    let fold_count = match get_fold_count() {
+        Some(fold_count) => fold_count,
+        None => return false,
+    };
+    println!("Cross-validating over {} folds.", fold_count);
+    let result = evaluation::cross_validate(&train_matrix.merge_rows(test_matrix), fold_count, train, inverse_f);
+    println!("Cross-validation error:");
+    println!("-----------------------");
+    result.print();
+    true
+}
+
+/// Number of tanks to recommend.
+const RECOMMENDATION_COUNT: usize = 10;
+
+/// Prints recommendations for the row passed as `--recommend=ROW` or the account passed as `--recommend-account=ID`.
+///
+/// Recommended tanks are restricted by `--filter=QUERY`, e.g. `--filter="tier=8 class=heavy"`.
+/// With `--research` only the tanks researchable from the owned ones are recommended.
+/// Owned tanks are the ones rated in either train or test matrix, so tanks dropped while reading are not considered owned.
+#[allow(dead_code)]
+pub fn print_recommendations<C: csr::Index, V: csr::Value>(model: &AbstractModel<C, V>, train_matrix: &csr::Csr<C, V>, test_matrix: &csr::Csr<C, V>, encyclopedia: &encyclopedia::Encyclopedia, accounts: &accounts::AccountIndex) {
+    let row_index: usize = match (get_option("recommend"), get_option("recommend-account")) {
+        (Some(value), _) => value.parse().unwrap(),
+        (None, Some(value)) => match accounts.find_row(value.parse().unwrap()) {
+            Some(row_index) => row_index,
+            None => panic!("account #{} is not found", value),
+        },
+        (None, None) => return,
+    };
+    let account_id = accounts.get_account_id(row_index);
+    let filter = match get_option("filter") {
+        Some(query) => filter::Filter::parse(&query).unwrap_or_else(|error| panic!("invalid filter: {}", error)),
+        None => filter::Filter::new(),
+    };
+    let columns = filter.get_columns(encyclopedia);
+    let owned: Vec<usize> = train_matrix.get_row(row_index).iter().chain(test_matrix.get_row(row_index))
+        .map(|value| value.column.to_usize()).collect();
+
+    let recommendations = if has_flag("--research") {
+        println!("Research recommendations for account #{} (row #{}) out of {} matching tanks:", account_id, row_index, columns.len());
+        filter::recommend_research(model, train_matrix, row_index, encyclopedia, &owned, &columns, RECOMMENDATION_COUNT)
+    } else {
+        println!("Recommendations for account #{} (row #{}) out of {} matching tanks:", account_id, row_index, columns.len());
+        filter::recommend(model, train_matrix, row_index, &owned, &columns, RECOMMENDATION_COUNT)
+    };
+    for (column, rating) in recommendations {
+        println!("  {0:>36}: {1:6.2}%", encyclopedia.get_name(encyclopedia.get_tank_id(column)), rating);
+    }
 }
 
 /// Evaluates model error distribution.
-pub fn evaluate_error_distribution<F>(model: &AbstractModel, train_matrix: &csr::Csr, test_matrix: &csr::Csr, inverse_f: F) -> Vec<f64>
+pub fn evaluate_error_distribution<C: csr::Index, V: csr::Value, F>(model: &AbstractModel<C, V>, train_matrix: &csr::Csr<C, V>, test_matrix: &csr::Csr<C, V>, inverse_f: F) -> Vec<f64>
     where F : Fn(f64) -> f64 {
 
     let mut distribution = vec![0.0; 102];
@@ -69,8 +247,8 @@ pub fn evaluate_error_distribution<F>(model: &AbstractModel, train_matrix: &csr:
 
     for row_index in 0..test_matrix.row_count() {
         for actual_value in test_matrix.get_row(row_index) {
-            if let Some(predicted_value) = model.predict(train_matrix, row_index, actual_value.column) {
-                let error = inverse_f(predicted_value) - inverse_f(actual_value.value);
+            if let Some(predicted_value) = model.predict(train_matrix, row_index, actual_value.column.to_usize()) {
+                let error = inverse_f(predicted_value) - inverse_f(actual_value.value.to_f64());
                 distribution[error.abs().min(101.0).round() as usize] += increment;
             }
         }
@@ -92,67 +270,751 @@ pub fn print_error_distribution(distribution: Vec<f64>) {
     }
 }
 
+/// Gets statistics file path.
+fn get_input_path() -> PathBuf {
+    use std::env::args;
+
+    PathBuf::from(&args().nth(1).unwrap())
+}
+
 /// Gets statistics input.
-fn get_input() -> BufReader<File> {
+///
+/// Gzip and Zstandard compressed files are decompressed transparently.
+fn get_input(path: &Path) -> input::Input {
+    let input = input::Input::open(path).unwrap();
+    println!("Input compression: {:?}.", input.compression());
+    input
+}
+
+/// Gets whether the flag is passed after the input path.
+fn has_flag(flag: &str) -> bool {
     use std::env::args;
-    use std::path::Path;
 
-    let input_file = File::open(&Path::new(&args().nth(1).unwrap())).unwrap();
-    BufReader::with_capacity(1024 * 1024, input_file)
+    args().skip(2).any(|arg| arg == flag)
 }
 
-/// Reads statistics file.
+/// Gets the value of `--name=value` option.
+fn get_option(name: &str) -> Option<String> {
+    use std::env::args;
+
+    let prefix = format!("--{}=", name);
+    args().skip(2).find(|arg| arg.starts_with(&prefix)).map(|arg| arg[prefix.len()..].to_string())
+}
+
+/// Gets tankopedia.
+///
+/// Loads it from `--encyclopedia=PATH` if passed, otherwise uses the built-in one.
+/// Tech tree is loaded from `--tech-tree=PATH`.
+///
+/// `--filter` requires `--encyclopedia`: the built-in one has no tank metadata, so every filter would match nothing.
+fn get_encyclopedia() -> encyclopedia::Encyclopedia {
+    if get_option("filter").is_some() && get_option("encyclopedia").is_none() {
+        panic!("--filter requires tank metadata from --encyclopedia=PATH");
+    }
+    let mut encyclopedia = match get_option("encyclopedia") {
+        Some(path) => match encyclopedia::Encyclopedia::load(Path::new(&path)) {
+            Ok(encyclopedia) => {
+                println!("Loaded {} tanks from {}.", encyclopedia.len(), path);
+                encyclopedia
+            }
+            Err(error) => panic!("failed to load encyclopedia: {}", error),
+        },
+        None => encyclopedia::Encyclopedia::new(),
+    };
+    if let Some(path) = get_option("tech-tree") {
+        if let Err(error) = encyclopedia.load_tech_tree(Path::new(&path)) {
+            panic!("failed to load tech tree: {}", error);
+        }
+    }
+    encyclopedia
+}
+
+/// Fingerprint file line with the tanks added to the encyclopedia while reading, in the column order.
+const ADDED_TANKS_PREFIX: &'static str = "added tanks:";
+
+/// Gets train matrix, test matrix, account index and fingerprint cache paths for `--cache=PREFIX`.
+fn get_cache_paths(prefix: &str) -> (PathBuf, PathBuf, PathBuf, PathBuf) {
+    (
+        PathBuf::from(format!("{}.train.csr", prefix)),
+        PathBuf::from(format!("{}.test.csr", prefix)),
+        PathBuf::from(format!("{}.accounts", prefix)),
+        PathBuf::from(format!("{}.fingerprint", prefix)),
+    )
+}
+
+/// Describes everything the matrices are made from, one `key: value` pair per line.
 ///
-/// Returns train rating matrix and test rating matrix.
-fn read_stats<R: Read, F>(input: &mut R, min_battles: u32, encyclopedia: &encyclopedia::Encyclopedia, f: F) -> (csr::Csr, csr::Csr)
+/// The rating function is described by the hash of its values and the tanks by the hash of their IDs in the column order.
+fn get_fingerprint<F>(input_path: &Path, options: ReadOptions, encyclopedia: &encyclopedia::Encyclopedia, f: &F) -> String
     where F : Fn(f64) -> f64 {
 
-    use rand::{Rng, thread_rng};
-    use time::now;
+    use std::time::UNIX_EPOCH;
+
+    let (size, modified) = match input_path.metadata() {
+        Ok(metadata) => (
+            metadata.len(),
+            metadata.modified().ok().and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |duration| duration.as_secs() * 1000000000 + duration.subsec_nanos() as u64),
+        ),
+        Err(_) => (0, 0),
+    };
+    let mut ratings = Vec::new();
+    for step in 0..11 {
+        checksum::write_u64(&mut ratings, f(MAX_RATING * step as f64 / 10.0).to_bits()).unwrap();
+    }
+    let mut tank_ids = Vec::new();
+    for column in 0..encyclopedia.len() {
+        checksum::write_le(&mut tank_ids, encyclopedia.get_tank_id(column) as u64, 2).unwrap();
+    }
+    let strategy = match options.strategy {
+        // The seed is random unless the strategy is given explicitly, so the cached split is as good as a new one.
+        split::Strategy::TankHoldout { ratio, .. } => format!("TankHoldout({})", ratio),
+        strategy => format!("{:?}", strategy),
+    };
+    format!(
+        concat!(
+            "input: {}\ninput size: {}\ninput modified: {}\nmin battles: {}\nrating function: {:016x}\n",
+            "read mode: {:?}\nunknown tanks: {:?}\nsplit strategy: {}\ntanks: {:016x}\n"
+        ),
+        input_path.display(), size, modified, options.min_battles, checksum::hash(&ratings),
+        options.mode, options.policy, strategy, checksum::hash(&tank_ids),
+    )
+}
+
+/// Loads train and test matrices and their account index from the cache.
+///
+/// Tanks added while reading the cached matrices are added to the encyclopedia again.
+/// Returns `None` and keeps the encyclopedia if the cache is missing, corrupt, made from something else
+/// than the fingerprint describes or refers to columns unknown to the encyclopedia.
+fn load_cache<C: csr::Index, V: csr::Value>(prefix: &str, fingerprint: &str, encyclopedia: &mut encyclopedia::Encyclopedia) -> Option<(csr::Csr<C, V>, csr::Csr<C, V>, accounts::AccountIndex)> {
+    use std::fs::File;
+    use std::io::Read;
+
+    let start_time = time::now();
+    let (train_path, test_path, accounts_path, fingerprint_path) = get_cache_paths(prefix);
+    if !train_path.exists() || !test_path.exists() || !accounts_path.exists() {
+        return None;
+    }
+    let mut cached_fingerprint = String::new();
+    if let Err(error) = File::open(&fingerprint_path).and_then(|mut file| file.read_to_string(&mut cached_fingerprint)) {
+        println!("Ignoring cache: failed to read {}: {}.", fingerprint_path.display(), error);
+        return None;
+    }
+    let (cached_fingerprint, added_tanks) = match split_added_tanks(&cached_fingerprint) {
+        Ok(result) => result,
+        Err(error) => {
+            println!("Ignoring cache: {}.", error);
+            return None;
+        }
+    };
+    if cached_fingerprint != fingerprint {
+        match cached_fingerprint.lines().zip(fingerprint.lines()).find(|&(cached_line, line)| cached_line != line) {
+            Some((cached_line, line)) => println!("Ignoring cache: it is made with {:?} instead of {:?}.", cached_line, line),
+            None => println!("Ignoring cache: fingerprint mismatch."),
+        }
+        return None;
+    }
+    let matrices = csr::Csr::load(&train_path).and_then(|train_matrix| csr::Csr::load(&test_path).map(|test_matrix| (train_matrix, test_matrix)));
+    let matrices = matrices.and_then(|(train_matrix, test_matrix)| {
+        accounts::AccountIndex::load(&accounts_path).map(|accounts| (train_matrix, test_matrix, accounts))
+    });
+    match matrices {
+        Ok((train_matrix, test_matrix, accounts)) => {
+            let mut grown_encyclopedia = encyclopedia.clone();
+            for tank_id in added_tanks {
+                if grown_encyclopedia.contains(tank_id) {
+                    println!("Ignoring cache: added tank #{} is already in the encyclopedia.", tank_id);
+                    return None;
+                }
+                grown_encyclopedia.add(tank_id);
+            }
+            if train_matrix.column_count().max(test_matrix.column_count()) > grown_encyclopedia.len() {
+                println!("Ignoring cache: it has more columns than the encyclopedia.");
+                return None;
+            }
+            if accounts.len() != train_matrix.row_count() || accounts.len() != test_matrix.row_count() {
+                println!("Ignoring cache: account count does not match row count.");
+                return None;
+            }
+            println!(
+                "Loaded {} train and {} test values from cache in {:.1}s. {} rows.",
+                train_matrix.len(), test_matrix.len(), get_seconds(start_time), train_matrix.row_count()
+            );
+            *encyclopedia = grown_encyclopedia;
+            Some((train_matrix, test_matrix, accounts))
+        }
+        Err(error) => {
+            println!("Ignoring cache: {}.", error);
+            None
+        }
+    }
+}
+
+/// Saves train and test matrices, their account index and fingerprint to the cache.
+///
+/// Tanks added to the encyclopedia while reading are saved along with the fingerprint.
+fn save_cache<C: csr::Index, V: csr::Value>(prefix: &str, fingerprint: &str, train_matrix: &csr::Csr<C, V>, test_matrix: &csr::Csr<C, V>, accounts: &accounts::AccountIndex, added_tanks: &[u16]) {
+    use std::fs::File;
+    use std::io::Write;
+
+    let (train_path, test_path, accounts_path, fingerprint_path) = get_cache_paths(prefix);
+    let result = train_matrix.save(&train_path)
+        .and_then(|_| test_matrix.save(&test_path))
+        .and_then(|_| accounts.save(&accounts_path))
+        .and_then(|_| File::create(&fingerprint_path).and_then(|mut file| {
+            try!(file.write_all(fingerprint.as_bytes()));
+            try!(write!(file, "{}", ADDED_TANKS_PREFIX));
+            for tank_id in added_tanks {
+                try!(write!(file, " {}", tank_id));
+            }
+            writeln!(file)
+        }));
+    if let Err(error) = result {
+        panic!("failed to save cache: {}", error);
+    }
+    println!(
+        "Saved cache to {}, {}, {} and {}.",
+        train_path.display(), test_path.display(), accounts_path.display(), fingerprint_path.display()
+    );
+}
+
+/// Splits the fingerprint file contents into the fingerprint and the added tanks.
+fn split_added_tanks(contents: &str) -> Result<(&str, Vec<u16>), String> {
+    let start = match contents.rfind(&format!("\n{}", ADDED_TANKS_PREFIX)) {
+        Some(start) => start + 1,
+        None => return Err("added tanks are missing in the fingerprint".to_string()),
+    };
+    let tank_ids = try!(contents[(start + ADDED_TANKS_PREFIX.len())..].split_whitespace()
+        .map(|tank_id| tank_id.parse().map_err(|_| format!("invalid added tank ID: {:?}", tank_id)))
+        .collect());
+    Ok((&contents[..start], tank_ids))
+}
+
+/// Gets k-core minimums passed as `--k-core=TANKS,ACCOUNTS`.
+///
+/// Returns minimum tank count per account and minimum account count per tank.
+fn get_k_core() -> Option<(usize, usize)> {
+    get_option("k-core").map(|value| {
+        let counts: Vec<usize> = value.split(',').map(|count| count.parse().unwrap()).collect();
+        match counts.len() {
+            1 => (counts[0], counts[0]),
+            2 => (counts[0], counts[1]),
+            _ => panic!("k-core must be TANKS,ACCOUNTS: {}", value),
+        }
+    })
+}
+
+/// Prunes train matrix to its k-core and drops the pruned rows and columns from the test matrix and the account index.
+///
+/// Pruned rows are removed, pruned columns are left empty so that columns still match the encyclopedia.
+fn prune<C: csr::Index, V: csr::Value>(train_matrix: csr::Csr<C, V>, test_matrix: csr::Csr<C, V>, accounts: &accounts::AccountIndex, column_count: usize, min_tank_count: usize, min_account_count: usize) -> (csr::Csr<C, V>, csr::Csr<C, V>, accounts::AccountIndex) {
+    let csr::Submatrix { matrix: mut train_matrix, rows, columns } = train_matrix.k_core(column_count, min_tank_count, min_account_count);
+    let mut is_column_kept = vec![false; column_count];
+    for &column in &columns {
+        is_column_kept[column] = true;
+    }
+    let (mut test_matrix, _) = test_matrix.select_rows(&rows).drop_columns(column_count, |column| !is_column_kept[column]);
+    train_matrix.map_columns(|column| columns[column]);
+    test_matrix.map_columns(|column| columns[column]);
+    println!(
+        "Pruned to {} rows and {} columns with {} train and {} test values.",
+        train_matrix.row_count(), columns.len(), train_matrix.len(), test_matrix.len()
+    );
+    (train_matrix, test_matrix, accounts.select(&rows))
+}
+
+/// Gets number of cross-validation folds passed as `--folds=K`.
+fn get_fold_count() -> Option<usize> {
+    get_option("folds").map(|value| value.parse().unwrap())
+}
+
+/// Gets corrupt accounts handling mode.
+///
+/// Corrupt accounts are skipped if `--skip-corrupt` is passed.
+fn get_read_mode() -> ReadMode {
+    if has_flag("--skip-corrupt") { ReadMode::SkipCorrupt } else { ReadMode::Strict }
+}
+
+/// Gets unknown tanks handling policy.
+///
+/// Records of unknown tanks are skipped unless `--unknown-tanks=grow` is passed.
+fn get_unknown_tank_policy() -> UnknownTankPolicy {
+    match get_option("unknown-tanks") {
+        None => UnknownTankPolicy::Skip,
+        Some(ref value) if value == "skip" => UnknownTankPolicy::Skip,
+        Some(ref value) if value == "grow" => UnknownTankPolicy::Grow,
+        Some(value) => panic!("unknown tanks policy must be skip or grow: {}", value),
+    }
+}
+
+/// Gets train/test split strategy.
+///
+/// Ratings are split at random with the default ratio unless `--split=STRATEGY` is passed, see `split::Strategy::parse`.
+/// The cache is ignored if it was saved with another strategy.
+///
+/// `--split` can't be combined with `--folds`: cross-validation assigns the folds on its own,
+/// so the strategy would be silently ignored.
+fn get_split_strategy() -> split::Strategy {
+    match get_option("split") {
+        Some(_) if get_fold_count().is_some() => panic!("--split can't be combined with --folds"),
+        Some(value) => split::Strategy::parse(&value).unwrap_or_else(|error| panic!("invalid split strategy: {}", error)),
+        None => split::Strategy::Random(split::DEFAULT_RATIO),
+    }
+}
+
+/// Gets number of threads to read statistics.
+///
+/// Uncompressed binary statistics are read in parallel unless `--threads=1` is passed.
+fn get_thread_count() -> usize {
+    get_option("threads").map_or(DEFAULT_THREAD_COUNT, |value| value.parse().unwrap())
+}
+
+/// Gets whether the decompressed input is text rather than binary statistics.
+///
+/// Text starts with a digit of the first account ID or with a header made of ASCII words.
+fn is_text(input: &mut input::Input) -> Result<bool, String> {
+    use std::io::BufRead;
+
+    let buffer = match input.fill_buf() {
+        Ok(buffer) => buffer,
+        Err(_) => return Ok(false),
+    };
+    match buffer.first() {
+        None | Some(&stats::HEADER_MAGIC) => Ok(false),
+        Some(&byte) if (byte as char).is_digit(10) || is_text_header(buffer) => Ok(true),
+        Some(&byte) => Err(format!("unknown input format starting with byte {:#04x}", byte)),
+    }
+}
+
+/// Gets whether the first line is a text header such as `account_id,tank_id,battles,wins`.
+fn is_text_header(buffer: &[u8]) -> bool {
+    buffer.iter()
+        .take_while(|&&byte| byte != b'\n')
+        .all(|&byte| (byte < 0x80 && (byte as char).is_alphanumeric()) || b"_-,\t \r".contains(&byte))
+}
+
+/// Reading summary of an account source.
+struct Summary {
+    /// Number of accounts read including the skipped ones.
+    account_count: usize,
+    /// Errors of the skipped corrupt accounts.
+    skipped: Vec<stats::Error>,
+    /// Maps unknown tank ID into its record count.
+    unknown_tanks: BTreeMap<u16, usize>,
+    /// Tank IDs added to the encyclopedia in the column order.
+    added_tanks: Vec<u16>,
+    /// Account IDs of the rows.
+    accounts: accounts::AccountIndex,
+}
+
+impl Summary {
+    fn new() -> Self {
+        Summary {
+            account_count: 0,
+            skipped: Vec::new(),
+            unknown_tanks: BTreeMap::new(),
+            added_tanks: Vec::new(),
+            accounts: accounts::AccountIndex::new(),
+        }
+    }
+}
+
+/// Reads statistics file.
+///
+/// Returns train rating matrix, test rating matrix and account index of their rows.
+fn read_stats<S: AccountSource, C: csr::Index, V: csr::Value, F>(source: &mut S, counter: &input::Counter, options: ReadOptions, encyclopedia: &mut encyclopedia::Encyclopedia, f: F) -> Result<(csr::Csr<C, V>, csr::Csr<C, V>, accounts::AccountIndex), stats::Error>
+    where F : Fn(f64) -> f64 {
 
     let start_time = time::now();
-    let mut rng = thread_rng();
 
-    let mut train_matrix = csr::Csr::new();
-    let mut test_matrix = csr::Csr::new();
+    let mut train_matrix = csr::CsrBuilder::default();
+    let mut test_matrix = csr::CsrBuilder::default();
 
     println!("Reading started at {}.", start_time.ctime());
 
-    for i in 1.. {
-        if i % 100000 == 0 {
-            println!(
-                "Reading | acc.: {} | {:.1} acc/s | train: {} | test: {}",
-                i, i as f32 / get_seconds(start_time), train_matrix.len(), test_matrix.len()
-            );
+    let summary = try!(read_accounts(
+        source, options, encyclopedia, &f, &mut train_matrix, &mut test_matrix,
+        |account_count, source, train_matrix, test_matrix| println!(
+            "Reading | acc.: {} | {:.1} acc/s | read: {:.1} MiB | decoded: {:.1} MiB | train: {} | test: {}",
+            account_count, account_count as f32 / get_seconds(start_time), to_mib(counter.get()),
+            to_mib(source.offset()), train_matrix.len(), test_matrix.len()
+        )
+    ));
+    let (train_matrix, test_matrix) = (train_matrix.build(), test_matrix.build());
+
+    println!(
+        "Read {1} train and {2} test values in {0:.1}s. {3} rows.",
+        get_seconds(start_time), train_matrix.len(), test_matrix.len(), train_matrix.row_count()
+    );
+    println!(
+        "Read {:.1} MiB, decoded {:.1} MiB.",
+        to_mib(counter.get()), to_mib(source.offset())
+    );
+    print_skipped(&summary.skipped);
+    print_unknown_tanks(&summary, options.policy);
+
+    Ok((train_matrix, test_matrix, summary.accounts))
+}
+
+/// Reads uncompressed binary statistics file on multiple threads.
+///
+/// Returns train rating matrix, test rating matrix and account index of their rows. Rows follow the file order.
+/// Tanks added by chunks are merged into the encyclopedia in the file order.
+fn read_stats_parallel<C: csr::Index, V: csr::Value, F>(path: &Path, thread_count: usize, options: ReadOptions, encyclopedia: &mut encyclopedia::Encyclopedia, f: F) -> Result<(csr::Csr<C, V>, csr::Csr<C, V>, accounts::AccountIndex), stats::Error>
+    where F : Fn(f64) -> f64 + Send + Sync + 'static {
+
+    use std::cell::Cell;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let start_time = time::now();
+    let shared_encyclopedia = Arc::new(encyclopedia.clone());
+    let column_count = encyclopedia.len();
+    // Totals of all threads for the progress.
+    let account_counter = Arc::new(AtomicUsize::new(0));
+    let decoded_counter = Arc::new(AtomicUsize::new(0));
+
+    println!("Reading started at {} on {} threads.", start_time.ctime(), thread_count);
+
+    let chunks = try!(parallel::map_chunks(path, thread_count, move |chunk| {
+        let mut encyclopedia = (*shared_encyclopedia).clone();
+        let mut train_matrix = csr::CsrBuilder::default();
+        let mut test_matrix = csr::CsrBuilder::default();
+        let chunk_offset = Cell::new(0);
+        let result = read_accounts(
+            &mut stats::AccountReader::new(chunk.data), options, &mut encyclopedia, &f, &mut train_matrix, &mut test_matrix,
+            |_, source, _, _| {
+                let offset = source.offset() as usize;
+                let account_count = account_counter.fetch_add(PROGRESS_INTERVAL, Ordering::Relaxed) + PROGRESS_INTERVAL;
+                let decoded = decoded_counter.fetch_add(offset - chunk_offset.get(), Ordering::Relaxed) + offset - chunk_offset.get();
+                chunk_offset.set(offset);
+                println!(
+                    "Reading | acc.: {} | {:.1} acc/s | decoded: {:.1} MiB",
+                    account_count, account_count as f32 / get_seconds(start_time), to_mib(decoded as u64)
+                );
+            });
+        (chunk.offset, result, train_matrix.build(), test_matrix.build())
+    }, |&(_, ref result, _, _)| match *result {
+        // The chunk ends in the middle of an account, so the boundary after it is wrong.
+        Err(stats::Error { kind: stats::ErrorKind::TruncatedRecord, .. }) => false,
+        _ => true,
+    }).map_err(|error| stats::Error { kind: stats::ErrorKind::Io(error), offset: 0, account_index: 0 }));
+
+    let mut train_matrix = csr::Csr::default();
+    let mut test_matrix = csr::Csr::default();
+    let mut summary = Summary::new();
+
+    // Merge chunks making error positions relative to the whole file.
+    for (offset, result, mut chunk_train_matrix, mut chunk_test_matrix) in chunks {
+        let chunk_summary = match result {
+            Ok(chunk_summary) => chunk_summary,
+            Err(mut error) => {
+                error.offset += offset as u64;
+                error.account_index += summary.account_count;
+                return Err(error);
+            }
+        };
+        for mut error in chunk_summary.skipped {
+            error.offset += offset as u64;
+            error.account_index += summary.account_count;
+            summary.skipped.push(error);
+        }
+        for (tank_id, count) in chunk_summary.unknown_tanks {
+            *summary.unknown_tanks.entry(tank_id).or_insert(0) += count;
         }
+        if !chunk_summary.added_tanks.is_empty() {
+            // Chunk columns past the initial ones are local to the chunk.
+            let mut columns = Vec::new();
+            for tank_id in chunk_summary.added_tanks {
+                if !encyclopedia.contains(tank_id) {
+                    summary.added_tanks.push(tank_id);
+                }
+                columns.push(encyclopedia.add(tank_id));
+            }
+            let map_column = |column: usize| if column < column_count { column } else { columns[column - column_count] };
+            chunk_train_matrix.map_columns(&map_column);
+            chunk_test_matrix.map_columns(&map_column);
+            chunk_train_matrix.sort_rows();
+            chunk_test_matrix.sort_rows();
+        }
+        summary.account_count += chunk_summary.account_count;
+        summary.accounts.append(chunk_summary.accounts);
+        train_matrix.append(chunk_train_matrix);
+        test_matrix.append(chunk_test_matrix);
+    }
 
-        train_matrix.start();
-        test_matrix.start();
+    println!(
+        "Read {1} train and {2} test values in {0:.1}s. {3} rows.",
+        get_seconds(start_time), train_matrix.len(), test_matrix.len(), train_matrix.row_count()
+    );
+    print_skipped(&summary.skipped);
+    print_unknown_tanks(&summary, options.policy);
+
+    Ok((train_matrix, test_matrix, summary.accounts))
+}
 
-        match stats::read_account(input) {
-            Some(account) => {
-                for tank in account.tanks {
-                    if tank.battles < min_battles {
+/// Reads accounts from the source into the train and test matrices.
+///
+/// Each account makes one row in both matrices and in the account index of the summary. Repeated records of the same tank are skipped.
+/// Calls `progress` every `PROGRESS_INTERVAL` accounts.
+fn read_accounts<S, C, V, F, P>(source: &mut S, options: ReadOptions, encyclopedia: &mut encyclopedia::Encyclopedia, f: &F, train_matrix: &mut csr::CsrBuilder<C, V>, test_matrix: &mut csr::CsrBuilder<C, V>, progress: P) -> Result<Summary, stats::Error>
+    where S: AccountSource, C: csr::Index, V: csr::Value, F : Fn(f64) -> f64, P: Fn(usize, &S, &csr::CsrBuilder<C, V>, &csr::CsrBuilder<C, V>) {
+
+    let mut splitter = split::Splitter::new(options.strategy);
+    let mut account = stats::Account { id: 0, tanks: Vec::new() };
+    let mut summary = Summary::new();
+    // Columns past this one are assigned to unknown tanks.
+    let known_column_count = encyclopedia.len();
+    // Column values of the current account.
+    let mut row = Vec::new();
+    let mut ratings = Vec::new();
+    let mut is_test = Vec::new();
+
+    loop {
+        match source.read_into(&mut account) {
+            Ok(true) => {
+                row.clear();
+                for tank in &account.tanks {
+                    if tank.battles < options.min_battles {
                         continue;
                     }
                     if tank.wins > tank.battles {
                         continue; // work around the bug in kit.py
                     }
-                    (if !rng.gen_weighted_bool(20) {
-                        &mut train_matrix
+                    let column = match encyclopedia.find_column(tank.id) {
+                        Some(column) => column,
+                        None if options.policy == UnknownTankPolicy::Skip => {
+                            *summary.unknown_tanks.entry(tank.id).or_insert(0) += 1;
+                            continue;
+                        }
+                        None => {
+                            summary.added_tanks.push(tank.id);
+                            encyclopedia.add(tank.id)
+                        }
+                    };
+                    if column >= known_column_count {
+                        *summary.unknown_tanks.entry(tank.id).or_insert(0) += 1;
+                    }
+                    let rating = split::Rating { tank_id: tank.id, battles: tank.battles };
+                    row.push((column, f(MAX_RATING * tank.wins as f64 / tank.battles as f64), rating));
+                }
+                // Stable sort keeps the first record of a repeated tank.
+                row.sort_by(|a, b| a.0.cmp(&b.0));
+                row.dedup_by_key(|&mut (column, _, _)| column);
+                ratings.clear();
+                ratings.extend(row.iter().map(|&(_, _, rating)| rating));
+                splitter.split(&ratings, &mut is_test);
+                for (&(column, value, _), &is_test) in row.iter().zip(&is_test) {
+                    (if !is_test {
+                        &mut *train_matrix
                     } else {
-                        &mut test_matrix
-                    }).next(encyclopedia.get_column(tank.id), f(MAX_RATING * tank.wins as f64 / tank.battles as f64));
+                        &mut *test_matrix
+                    }).next(C::from_usize(column), V::from_f64(value));
                 }
+                train_matrix.end_row();
+                test_matrix.end_row();
+                summary.accounts.push(account.id);
             }
-            None => break
+            Ok(false) => break,
+            Err(error) => {
+                if options.mode != ReadMode::SkipCorrupt || !error.is_recoverable() {
+                    return Err(error);
+                }
+                summary.skipped.push(error);
+            }
+        }
+        summary.account_count += 1;
+        if summary.account_count % PROGRESS_INTERVAL == 0 {
+            progress(summary.account_count, source, train_matrix, test_matrix);
         }
     }
 
-    println!(
-        "Read {1} train and {2} test values in {0:.1}s. {3} rows.",
-        get_seconds(start_time), train_matrix.len(), test_matrix.len(), train_matrix.row_count()
+    Ok(summary)
+}
+
+/// Prints errors of the skipped corrupt accounts.
+fn print_skipped(skipped: &[stats::Error]) {
+    for error in skipped {
+        println!("Skipped corrupt account: {}.", error);
+    }
+    if !skipped.is_empty() {
+        println!("Skipped {} corrupt accounts.", skipped.len());
+    }
+}
+
+/// Prints unknown tanks and their record counts.
+fn print_unknown_tanks(summary: &Summary, policy: UnknownTankPolicy) {
+    if summary.unknown_tanks.is_empty() {
+        return;
+    }
+    for (tank_id, count) in &summary.unknown_tanks {
+        println!("Unknown tank #{}: {} records.", tank_id, count);
+    }
+    let record_count = summary.unknown_tanks.values().fold(0, |acc, &count| acc + count);
+    match policy {
+        UnknownTankPolicy::Skip => println!(
+            "Skipped {} records of {} unknown tanks.", record_count, summary.unknown_tanks.len()),
+        UnknownTankPolicy::Grow => println!(
+            "Added {} unknown tanks with {} records to the encyclopedia.", summary.added_tanks.len(), record_count),
+    }
+}
+
+/// Converts bytes to mebibytes.
+fn to_mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+#[cfg(test)]
+fn make_read_options(min_battles: u32, policy: UnknownTankPolicy) -> ReadOptions {
+    ReadOptions { min_battles: min_battles, mode: ReadMode::Strict, policy: policy, strategy: split::Strategy::Random(split::DEFAULT_RATIO) }
+}
+
+#[test]
+fn test_read_accounts_unknown_tanks() {
+    use std::io::Cursor;
+
+    let input = "1,1,10,5\n1,65535,10,5\n2,65535,20,10\n2,65534,10,1\n";
+    let mut encyclopedia = encyclopedia::Encyclopedia::new();
+    let column_count = encyclopedia.len();
+    let mut train_matrix = csr::CsrBuilder::new();
+    let mut test_matrix = csr::CsrBuilder::new();
+    let summary = read_accounts(
+        &mut text::TextReader::new(Cursor::new(input.as_bytes())), make_read_options(10, UnknownTankPolicy::Skip), &mut encyclopedia,
+        &identity, &mut train_matrix, &mut test_matrix, |_, _, _, _| ()).unwrap();
+    assert_eq!(summary.account_count, 2);
+    assert_eq!(summary.accounts.len(), 2);
+    assert_eq!(summary.accounts.find_row(2), Some(1));
+    assert_eq!(summary.unknown_tanks.get(&65535), Some(&2));
+    assert_eq!(summary.unknown_tanks.get(&65534), Some(&1));
+    assert!(summary.added_tanks.is_empty());
+    assert_eq!(train_matrix.len() + test_matrix.len(), 1);
+    assert_eq!(encyclopedia.len(), column_count);
+
+    let mut train_matrix = csr::CsrBuilder::new();
+    let mut test_matrix = csr::CsrBuilder::new();
+    let summary = read_accounts(
+        &mut text::TextReader::new(Cursor::new(input.as_bytes())), make_read_options(10, UnknownTankPolicy::Grow), &mut encyclopedia,
+        &identity, &mut train_matrix, &mut test_matrix, |_, _, _, _| ()).unwrap();
+    assert_eq!(summary.added_tanks, vec![65535, 65534]);
+    assert_eq!(summary.unknown_tanks.get(&65535), Some(&2));
+    assert_eq!(train_matrix.len() + test_matrix.len(), 4);
+    assert_eq!(encyclopedia.find_column(65535), Some(column_count));
+    assert_eq!(encyclopedia.find_column(65534), Some(column_count + 1));
+}
+
+#[test]
+fn test_read_accounts_sorted() {
+    use std::io::Cursor;
+
+    let mut encyclopedia = encyclopedia::Encyclopedia::new();
+    let mut train_matrix = csr::CsrBuilder::new();
+    let mut test_matrix = csr::CsrBuilder::new();
+    read_accounts(
+        &mut text::TextReader::new(Cursor::new("1,81,10,5\n1,1,10,5\n1,81,20,20\n".as_bytes())), make_read_options(10, UnknownTankPolicy::Skip),
+        &mut encyclopedia, &identity, &mut train_matrix, &mut test_matrix, |_, _, _, _| ()).unwrap();
+    let (train_matrix, test_matrix) = (train_matrix.build(), test_matrix.build());
+    assert_eq!(train_matrix.row_count(), 1);
+    assert_eq!(test_matrix.row_count(), 1);
+    assert_eq!(train_matrix.len() + test_matrix.len(), 2);
+    let column = encyclopedia.get_column(81);
+    assert_eq!(train_matrix.get(0, column).or(test_matrix.get(0, column)), Some(50.0));
+}
+
+#[test]
+fn test_prune() {
+    let mut train_matrix = csr::CsrBuilder::new();
+    let mut test_matrix = csr::CsrBuilder::new();
+    for &(train_row, test_row) in &[(&[0, 1][..], &[2][..]), (&[0, 1, 2][..], &[3][..]), (&[3][..], &[0][..])] {
+        for &column in train_row {
+            train_matrix.next(column, column as f64);
+        }
+        for &column in test_row {
+            test_matrix.next(column, column as f64);
+        }
+        train_matrix.end_row();
+        test_matrix.end_row();
+    }
+    let accounts = accounts::AccountIndex::from_ids(vec![10, 20, 30]);
+    let (train_matrix, test_matrix, accounts) = prune(train_matrix.build(), test_matrix.build(), &accounts, 4, 2, 2);
+    assert_eq!(train_matrix.row_count(), 2);
+    assert_eq!(accounts.len(), 2);
+    assert_eq!(accounts.find_row(20), Some(1));
+    assert_eq!(accounts.find_row(30), None);
+    assert_eq!(test_matrix.row_count(), 2);
+    assert_eq!(train_matrix.len(), 4);
+    assert_eq!(train_matrix.get(1, 1), Some(1.0));
+    assert_eq!(test_matrix.len(), 0);
+}
+
+#[test]
+fn test_is_text_header() {
+    assert!(is_text_header(b"account_id,tank_id,battles,wins\n1,1,1,1\n"));
+    assert!(is_text_header(b"Account ID\tTank ID\tBattles\tWins\r\n"));
+    assert!(!is_text_header(b"\x1f\x8b\x08\x00"));
+    assert!(!is_text_header(b"{\"status\": \"ok\"}\n"));
+}
+
+#[test]
+fn test_get_fingerprint() {
+    let encyclopedia = encyclopedia::Encyclopedia::new();
+    let path = Path::new("missing.bin");
+    let options = make_read_options(10, UnknownTankPolicy::Skip);
+    let fingerprint = get_fingerprint(path, options, &encyclopedia, &identity);
+    assert!(fingerprint.starts_with("input: missing.bin\ninput size: 0\n"));
+    assert_eq!(get_fingerprint(path, options, &encyclopedia, &identity), fingerprint);
+    assert!(get_fingerprint(path, ReadOptions { min_battles: 20, ..options }, &encyclopedia, &identity) != fingerprint);
+    assert!(get_fingerprint(path, options, &encyclopedia, &|value: f64| value / MAX_RATING) != fingerprint);
+    assert!(get_fingerprint(path, ReadOptions { strategy: split::Strategy::LeaveKOut(1), ..options }, &encyclopedia, &identity) != fingerprint);
+    assert_eq!(
+        get_fingerprint(path, ReadOptions { strategy: split::Strategy::TankHoldout { ratio: 0.1, seed: 1 }, ..options }, &encyclopedia, &identity),
+        get_fingerprint(path, ReadOptions { strategy: split::Strategy::TankHoldout { ratio: 0.1, seed: 2 }, ..options }, &encyclopedia, &identity)
     );
+}
+
+#[test]
+fn test_save_load_cache_grown() {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    let mut encyclopedia = encyclopedia::Encyclopedia::new();
+    let column_count = encyclopedia.len();
+    let mut train_matrix = csr::CsrBuilder::new();
+    let mut test_matrix = csr::CsrBuilder::new();
+    train_matrix.next(0, 50.0);
+    train_matrix.next(column_count + 1, 40.0);
+    test_matrix.next(column_count, 60.0);
+    train_matrix.end_row();
+    test_matrix.end_row();
+    let (train_matrix, test_matrix): (csr::Csr, csr::Csr) = (train_matrix.build(), test_matrix.build());
+    let accounts = accounts::AccountIndex::from_ids(vec![42]);
+
+    let prefix = env::temp_dir().join(format!("rusty-tank-test-{}", process::id()));
+    let prefix = prefix.to_str().unwrap();
+    save_cache(prefix, "input: test\n", &train_matrix, &test_matrix, &accounts, &[65535, 65534]);
+
+    // Another fingerprint keeps the encyclopedia.
+    assert!(load_cache::<usize, f64>(prefix, "input: other\n", &mut encyclopedia).is_none());
+    assert_eq!(encyclopedia.len(), column_count);
+
+    let (loaded_train_matrix, loaded_test_matrix, loaded_accounts) = load_cache::<usize, f64>(prefix, "input: test\n", &mut encyclopedia).unwrap();
+    assert_eq!(encyclopedia.len(), column_count + 2);
+    assert_eq!(encyclopedia.find_column(65535), Some(column_count));
+    assert_eq!(encyclopedia.get_tank_id(column_count + 1), 65534);
+    assert_eq!(loaded_train_matrix.get(0, column_count + 1), Some(40.0));
+    assert_eq!(loaded_test_matrix.get(0, column_count), Some(60.0));
+    assert_eq!(loaded_accounts.get_account_id(0), 42);
+
+    let (train_path, test_path, accounts_path, fingerprint_path) = get_cache_paths(prefix);
+    for path in &[train_path, test_path, accounts_path, fingerprint_path] {
+        fs::remove_file(path).unwrap();
+    }
+}
 
-    (train_matrix, test_matrix)
+#[test]
+fn test_split_added_tanks() {
+    assert_eq!(split_added_tanks("input: a\nadded tanks: 1 65535\n").unwrap(), ("input: a\n", vec![1, 65535]));
+    assert_eq!(split_added_tanks("input: a\nadded tanks:\n").unwrap(), ("input: a\n", vec![]));
+    assert!(split_added_tanks("input: a\n").is_err());
+    assert!(split_added_tanks("input: a\nadded tanks: 70000\n").is_err());
 }
@@ -0,0 +1,177 @@
+//! Statistics input with transparent decompression.
+
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::rc::Rc;
+
+use flate2;
+use zstd;
+
+/// Input buffer size.
+const BUFFER_SIZE: usize = 1024 * 1024;
+/// Gzip magic bytes.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Zstandard magic bytes.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// Detects compression by the magic bytes at the beginning of the input.
+pub fn detect_compression(header: &[u8]) -> Compression {
+    if header.starts_with(&GZIP_MAGIC) {
+        Compression::Gzip
+    } else if header.starts_with(&ZSTD_MAGIC) {
+        Compression::Zstd
+    } else {
+        Compression::None
+    }
+}
+
+/// Shared counter of bytes read from the underlying input.
+#[derive(Clone)]
+pub struct Counter(Rc<Cell<u64>>);
+
+impl Counter {
+    fn new() -> Self {
+        Counter(Rc::new(Cell::new(0)))
+    }
+
+    /// Gets the counter value.
+    pub fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    fn add(&self, value: u64) {
+        self.0.set(self.0.get() + value);
+    }
+}
+
+/// Buffered statistics input.
+///
+/// Decompresses the underlying file if needed and counts compressed bytes read.
+pub struct Input {
+    reader: Box<BufRead>,
+    compression: Compression,
+    compressed_counter: Counter,
+}
+
+impl Input {
+    /// Opens the file and detects its compression.
+    pub fn open(path: &Path) -> io::Result<Input> {
+        Input::new(try!(File::open(path)))
+    }
+
+    /// Wraps the reader detecting its compression.
+    pub fn new<R: Read + 'static>(input: R) -> io::Result<Input> {
+        let compressed_counter = Counter::new();
+        let mut reader = BufReader::with_capacity(
+            BUFFER_SIZE, CountingRead { input: input, counter: compressed_counter.clone() });
+        let compression = detect_compression(try!(reader.fill_buf()));
+        let reader: Box<BufRead> = match compression {
+            Compression::None => Box::new(reader),
+            Compression::Gzip => Box::new(BufReader::with_capacity(
+                BUFFER_SIZE, flate2::bufread::MultiGzDecoder::new(reader))),
+            Compression::Zstd => Box::new(BufReader::with_capacity(
+                BUFFER_SIZE, try!(zstd::Decoder::with_buffer(reader)))),
+        };
+        Ok(Input { reader: reader, compression: compression, compressed_counter: compressed_counter })
+    }
+
+    /// Gets detected compression.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Gets the counter of bytes read from the underlying input.
+    pub fn compressed_counter(&self) -> Counter {
+        self.compressed_counter.clone()
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buffer)
+    }
+}
+
+impl BufRead for Input {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount)
+    }
+}
+
+/// Counts bytes read from the underlying reader.
+struct CountingRead<R> {
+    input: R,
+    counter: Counter,
+}
+
+impl<R: Read> Read for CountingRead<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let length = try!(self.input.read(buffer));
+        self.counter.add(length as u64);
+        Ok(length)
+    }
+}
+
+#[test]
+fn test_detect_compression() {
+    assert_eq!(detect_compression(&[]), Compression::None);
+    assert_eq!(detect_compression(&[0x3e, 0x3e, 0x03]), Compression::None);
+    assert_eq!(detect_compression(&[0x1f, 0x8b, 0x08]), Compression::Gzip);
+    assert_eq!(detect_compression(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]), Compression::Zstd);
+}
+
+#[test]
+fn test_input_gzip() {
+    use std::io::{Cursor, Write};
+
+    let data = vec![0x3e, 0x3e, 0x03, 0x01, 0x8E, 0x02, 0x9E, 0xA7, 0x05, 0x9D, 0xA7, 0x05];
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let compressed_length = compressed.len() as u64;
+
+    let mut input = Input::new(Cursor::new(compressed)).unwrap();
+    assert_eq!(input.compression(), Compression::Gzip);
+    let mut output = Vec::new();
+    input.read_to_end(&mut output).unwrap();
+    assert_eq!(output, data);
+    assert_eq!(input.compressed_counter().get(), compressed_length);
+}
+
+#[test]
+fn test_input_zstd() {
+    use std::io::Cursor;
+
+    let data = vec![0x3e, 0x3e, 0x03, 0x00];
+    let compressed = zstd::encode_all(Cursor::new(data.clone()), 0).unwrap();
+
+    let mut input = Input::new(Cursor::new(compressed)).unwrap();
+    assert_eq!(input.compression(), Compression::Zstd);
+    let mut output = Vec::new();
+    input.read_to_end(&mut output).unwrap();
+    assert_eq!(output, data);
+}
+
+#[test]
+fn test_input_uncompressed() {
+    use std::io::Cursor;
+
+    let mut input = Input::new(Cursor::new(vec![0x3e, 0x3e, 0x01, 0x00])).unwrap();
+    assert_eq!(input.compression(), Compression::None);
+    let mut output = Vec::new();
+    input.read_to_end(&mut output).unwrap();
+    assert_eq!(output, vec![0x3e, 0x3e, 0x01, 0x00]);
+}
@@ -2,14 +2,26 @@
 ///
 /// Draft implementation.
 
+extern crate flate2;
+extern crate memmap;
 extern crate rand;
+extern crate rustc_serialize;
 extern crate time;
+extern crate zstd;
 
+mod accounts;
+mod checksum;
 mod csr;
 mod encyclopedia;
+mod evaluation;
+mod filter;
 mod helpers;
+mod input;
+mod parallel;
 mod protobuf;
+mod split;
 mod stats;
+mod text;
 
 const MIN_BATTLES: u32 = 10;
 const MIN_SHARED: usize = 3;
@@ -17,56 +29,46 @@ const MIN_CORRELATION: f64 = 0.0;
 
 /// Collaborative filtering model.
 struct Model {
-    row_count: usize,
-    /// Correlations between rows.
+    column_count: usize,
+    /// Correlations between columns.
     correlations: Vec<f64>,
 }
 
 impl Model {
-    pub fn new(row_count: usize) -> Model {
-        Model { row_count: row_count, correlations: vec![0.0; row_count * row_count] }
+    pub fn new(column_count: usize) -> Model {
+        Model { column_count: column_count, correlations: vec![0.0; column_count * column_count] }
     }
 
-    /// Trains the model.
-    ///
-    /// Items have to be placed by rows.
-    pub fn train(&mut self, matrix: &csr::Csr) {
-        for row_1 in 0..matrix.row_count() {
-            println!("Training | {} of {}.", row_1, matrix.row_count());
-            for row_2 in row_1..matrix.row_count() {
-                let correlation = pearson(matrix.get_row(row_1), matrix.get_row(row_2), MIN_SHARED);
-                self.correlations[row_1 * self.row_count + row_2] = correlation;
-                self.correlations[row_2 * self.row_count + row_1] = correlation;
+    /// Trains the model on the column-oriented view.
+    pub fn train(&mut self, matrix: &csr::CsrCsc) {
+        for column_1 in 0..matrix.column_count() {
+            println!("Training | {} of {}.", column_1, matrix.column_count());
+            for column_2 in column_1..matrix.column_count() {
+                let correlation = pearson(matrix.get_column(column_1), matrix.get_column(column_2), MIN_SHARED);
+                self.correlations[column_1 * self.column_count + column_2] = correlation;
+                self.correlations[column_2 * self.column_count + column_1] = correlation;
             }
         }
     }
 }
 
 impl helpers::AbstractModel for Model {
-    /// Quick and dirty implementation. :(
-    fn predict(&self, transposed_train_matrix: &csr::Csr, original_row_index: usize, original_column_index: usize) -> Option<f64> {
-        assert!(original_column_index < self.row_count, "{} < {}", original_column_index, self.row_count);
+    fn predict(&self, train_matrix: &csr::Csr, row_index: usize, column_index: usize) -> Option<f64> {
+        assert!(column_index < self.column_count, "{} < {}", column_index, self.column_count);
 
         let mut weight_sum = 0.0;
         let mut weighted_sum = 0.0;
 
-        for transposed_row_index in 0..transposed_train_matrix.row_count() {
-            if transposed_row_index == original_column_index {
-                // Skip the requested row (tank).
+        for value in train_matrix.get_row(row_index) {
+            if value.column == column_index {
                 continue;
             }
-            let weight = self.correlations[original_column_index * self.row_count + transposed_row_index];
+            let weight = self.correlations[column_index * self.column_count + value.column];
             if weight < MIN_CORRELATION {
                 continue;
             }
-            for transposed_value in transposed_train_matrix.get_row(transposed_row_index) {
-                if transposed_value.column == original_row_index {
-                    // Found the requested column (account).
-                    weight_sum += weight;
-                    weighted_sum += weight * transposed_value.value;
-                    break;
-                }
-            }
+            weight_sum += weight;
+            weighted_sum += weight * value.value;
         }
 
         if weight_sum.abs() > 0.000001 { Some(weighted_sum / weight_sum) } else { None }
@@ -74,13 +76,9 @@ impl helpers::AbstractModel for Model {
 }
 
 /// Gets Pearson correlation coefficient.
+///
+/// Both rows have to be sorted.
 fn pearson(a: csr::Row, b: csr::Row, min_shared: usize) -> f64 {
-    use std::collections::HashMap;
-    // Map A column indexes into corresponding values.
-    let mut a_map: HashMap<usize, f64> = HashMap::new();
-    for column_value in a {
-        a_map.insert(column_value.column, column_value.value);
-    }
     // Get the sums.
     let mut n = 0;
     let mut sum_a = 0.0;
@@ -88,15 +86,13 @@ fn pearson(a: csr::Row, b: csr::Row, min_shared: usize) -> f64 {
     let mut sum_squared_a = 0.0;
     let mut sum_squared_b = 0.0;
     let mut product_sum = 0.0;
-    for b_column_value in b {
-        if let Some(&a_value) = a_map.get(&b_column_value.column) {
-            n += 1;
-            sum_a += a_value;
-            sum_b += b_column_value.value;
-            sum_squared_a += a_value * a_value;
-            sum_squared_b += b_column_value.value * b_column_value.value;
-            product_sum += a_value * b_column_value.value;
-        }
+    for (_, a_value, b_value) in csr::intersect(a, b) {
+        n += 1;
+        sum_a += a_value;
+        sum_b += b_value;
+        sum_squared_a += a_value * a_value;
+        sum_squared_b += b_value * b_value;
+        product_sum += a_value * b_value;
     }
     // Get coefficient.
     if n < min_shared {
@@ -112,19 +108,34 @@ fn pearson(a: csr::Row, b: csr::Row, min_shared: usize) -> f64 {
 
 #[allow(dead_code)]
 fn main() {
-    let (encyclopedia, mut train_matrix, test_matrix) = helpers::get_stats(MIN_BATTLES, helpers::identity);
+    let (encyclopedia, train_matrix, test_matrix, accounts) = helpers::get_stats(MIN_BATTLES, helpers::identity);
+    if helpers::run_cross_validation(&train_matrix, &test_matrix, |train_matrix| {
+        let mut model = Model::new(encyclopedia.len());
+        model.train(&csr::CsrCsc::new(train_matrix.clone(), encyclopedia.len(), accounts.clone()));
+        model
+    }, helpers::identity) {
+        return;
+    }
     println!("Transposing.");
-    train_matrix.transpose();
+    let train_matrix = csr::CsrCsc::new(train_matrix, encyclopedia.len(), accounts);
     println!("Training.");
     let mut model = Model::new(encyclopedia.len());
     model.train(&train_matrix);
+    let (train_matrix, accounts) = train_matrix.into_csr();
     println!("Evaluating.");
-    let test_error = helpers::evaluate(&model, &train_matrix, &test_matrix, helpers::identity);
-    println!("Test error: {0:.6}.", test_error);
+    let test_report = helpers::evaluate(&model, &train_matrix, &test_matrix, helpers::identity);
+    println!("Test error: {}.", test_report);
     let error_distribution = helpers::evaluate_error_distribution(&model, &train_matrix, &test_matrix, helpers::identity);
     println!("Test error distribution:");
     println!("------------------------");
     helpers::print_error_distribution(error_distribution);
+    println!("Test error by tank:");
+    println!("-------------------");
+    test_report.print_columns(&encyclopedia);
+    println!("Test error by tier:");
+    println!("-------------------");
+    test_report.print_tiers(&encyclopedia);
+    helpers::print_recommendations(&model, &train_matrix, &test_matrix, &encyclopedia, &accounts);
 }
 
 #[test]
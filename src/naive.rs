@@ -1,64 +1,78 @@
 //! Naive rating prediction for Tankopoisk.
 
+extern crate flate2;
+extern crate memmap;
 extern crate rand;
+extern crate rustc_serialize;
 extern crate time;
+extern crate zstd;
 
+mod accounts;
+mod checksum;
 mod csr;
 mod encyclopedia;
+mod evaluation;
+mod filter;
 mod helpers;
+mod input;
+mod parallel;
 mod protobuf;
+mod split;
 mod stats;
+mod text;
 
 const MIN_BATTLES: u32 = 10;
 
 struct Model {
     column_count: usize,
-    average_rating: Vec<f64>,
+    /// Average rating of each column if it has any ratings.
+    average_rating: Vec<Option<f64>>,
 }
 
 impl Model {
     pub fn new(column_count: usize) -> Model {
-        Model { column_count: column_count, average_rating: vec![0.0f64; column_count] }
+        Model { column_count: column_count, average_rating: vec![None; column_count] }
     }
 
     pub fn train(&mut self, matrix: &csr::Csr) {
-        let mut rating_sum = vec![0.0f64; self.column_count];
-        let mut rating_count = vec![0; self.column_count];
-
-        for row_index in 0..matrix.row_count() {
-            let row = matrix.get_row(row_index);
-            for value in row {
-                rating_sum[value.column] += value.value;
-                rating_count[value.column] += 1;
-            }
-        }
-
-        for column_index in 0..(self.column_count - 1) {
-            self.average_rating[column_index] = rating_sum[column_index] / rating_count[column_index] as f64;
-        }
+        self.average_rating = matrix.column_means(self.column_count);
     }
 }
 
 impl helpers::AbstractModel for Model {
     #[allow(unused_variables)]
     fn predict(&self, train_matrix: &csr::Csr, row_index: usize, column_index: usize) -> Option<f64> {
-        Some(self.average_rating[column_index])
+        self.average_rating[column_index]
     }
 }
 
 #[allow(dead_code)]
 fn main() {
-    let (encyclopedia, train_matrix, test_matrix) = helpers::get_stats(MIN_BATTLES, helpers::identity);
+    let (encyclopedia, train_matrix, test_matrix, accounts) = helpers::get_stats(MIN_BATTLES, helpers::identity);
+    if helpers::run_cross_validation(&train_matrix, &test_matrix, |train_matrix| {
+        let mut model = Model::new(encyclopedia.len());
+        model.train(train_matrix);
+        model
+    }, helpers::identity) {
+        return;
+    }
     println!("Training.");
     let mut model = Model::new(encyclopedia.len());
     model.train(&train_matrix);
     println!("Evaluating.");
-    let train_error = helpers::evaluate(&model, &train_matrix, &train_matrix, helpers::identity);
-    println!("Train error: {0:.6}.", train_error);
-    let test_error = helpers::evaluate(&model, &train_matrix, &test_matrix, helpers::identity);
-    println!("Test error: {0:.6}.", test_error);
+    let train_report = helpers::evaluate(&model, &train_matrix, &train_matrix, helpers::identity);
+    println!("Train error: {}.", train_report);
+    let test_report = helpers::evaluate(&model, &train_matrix, &test_matrix, helpers::identity);
+    println!("Test error: {}.", test_report);
     let error_distribution = helpers::evaluate_error_distribution(&model, &train_matrix, &test_matrix, helpers::identity);
     println!("Test error distribution:");
     println!("------------------------");
     helpers::print_error_distribution(error_distribution);
+    println!("Test error by tank:");
+    println!("-------------------");
+    test_report.print_columns(&encyclopedia);
+    println!("Test error by tier:");
+    println!("-------------------");
+    test_report.print_tiers(&encyclopedia);
+    helpers::print_recommendations(&model, &train_matrix, &test_matrix, &encyclopedia, &accounts);
 }
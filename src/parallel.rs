@@ -0,0 +1,190 @@
+//! Parallel reading of memory-mapped statistics files.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use memmap::Mmap;
+
+use stats;
+
+/// Part of the file that contains whole accounts.
+pub struct Chunk<'a> {
+    /// Chunk offset in the file.
+    pub offset: usize,
+    pub data: &'a [u8],
+}
+
+/// Finds the first account that starts at or after the offset.
+///
+/// An account is considered valid if it is parsed successfully and
+/// is followed either by the end of data or by another header.
+/// Returns the data length if there is no such account.
+pub fn find_account_start(data: &[u8], offset: usize) -> usize {
+    let mut position = offset;
+    while position + 1 < data.len() {
+        if is_header_at(data, position) && is_account_at(data, position) {
+            return position;
+        }
+        position += 1;
+    }
+    data.len()
+}
+
+/// Splits data into the chunks of approximately equal size at account boundaries.
+///
+/// Returns chunk boundaries including zero and the data length.
+pub fn split(data: &[u8], chunk_count: usize) -> Vec<usize> {
+    let mut boundaries = vec![0];
+    for i in 1..chunk_count {
+        let start = find_account_start(data, data.len() * i / chunk_count);
+        if start > *boundaries.last().unwrap() {
+            boundaries.push(start);
+        }
+    }
+    if *boundaries.last().unwrap() != data.len() {
+        boundaries.push(data.len());
+    }
+    boundaries
+}
+
+/// Memory-maps the file and processes its chunks on separate threads.
+///
+/// A boundary may still be guessed wrong. So a chunk whose result is rejected by `is_complete`
+/// is processed again on the current thread together with the next one.
+/// Returns results in the chunk order.
+pub fn map_chunks<T, F, G>(path: &Path, chunk_count: usize, f: F, is_complete: G) -> io::Result<Vec<T>>
+    where T: Send + 'static, F: Fn(Chunk) -> T + Send + Sync + 'static, G: Fn(&T) -> bool {
+
+    let file = try!(File::open(path));
+    if try!(file.metadata()).len() == 0 {
+        // Empty files can't be mapped.
+        return Ok(vec![f(Chunk { offset: 0, data: &[] })]);
+    }
+    let mmap = Arc::new(try!(unsafe { Mmap::map(&file) }));
+    let boundaries = split(&mmap, chunk_count);
+    let f = Arc::new(f);
+
+    let handles: Vec<_> = (0..(boundaries.len() - 1)).map(|i| {
+        let (start, end) = (boundaries[i], boundaries[i + 1]);
+        let mmap = mmap.clone();
+        let f = f.clone();
+        thread::spawn(move || f(Chunk { offset: start, data: &mmap[start..end] }))
+    }).collect();
+
+    let mut results: Vec<(usize, usize, T)> = handles.into_iter().enumerate()
+        .map(|(i, handle)| (boundaries[i], boundaries[i + 1], handle.join().unwrap()))
+        .collect();
+
+    let mut i = 0;
+    while i + 1 < results.len() {
+        if is_complete(&results[i].2) {
+            i += 1;
+            continue;
+        }
+        let (start, end) = (results[i].0, results.remove(i + 1).1);
+        results[i] = (start, end, f(Chunk { offset: start, data: &mmap[start..end] }));
+    }
+
+    Ok(results.into_iter().map(|(_, _, result)| result).collect())
+}
+
+/// Gets whether a header with a supported format starts at the position.
+fn is_header_at(data: &[u8], position: usize) -> bool {
+    position + 1 < data.len() && data[position] == stats::HEADER_MAGIC && stats::is_supported_format(data[position + 1])
+}
+
+/// Gets whether a valid account starts at the position.
+fn is_account_at(data: &[u8], position: usize) -> bool {
+    let mut reader = stats::AccountReader::new(&data[position..]);
+    match reader.read_account() {
+        Ok(Some(_)) => (),
+        _ => return false,
+    }
+    let next_position = position + stats::AccountSource::offset(&reader) as usize;
+    next_position == data.len() || is_header_at(data, next_position)
+}
+
+#[cfg(test)]
+fn make_accounts(count: u32) -> (Vec<u8>, Vec<usize>) {
+    let mut data = Vec::new();
+    let mut starts = Vec::new();
+    for id in 0..count {
+        starts.push(data.len());
+        // Tank ID and battles contain header bytes on purpose.
+        let tanks = (0..(id % 5)).map(|i| stats::Tank::new(0x3e3e + i as u16, 0x3e, 1)).collect();
+        stats::write_account(&mut data, &stats::Account { id: id, tanks: tanks }).unwrap();
+    }
+    (data, starts)
+}
+
+#[test]
+fn test_find_account_start() {
+    let (data, starts) = make_accounts(10);
+    assert_eq!(find_account_start(&data, 0), 0);
+    for i in 1..starts.len() {
+        for offset in (starts[i - 1] + 1)..(starts[i] + 1) {
+            assert_eq!(find_account_start(&data, offset), starts[i]);
+        }
+    }
+    assert_eq!(find_account_start(&data, starts[9] + 1), data.len());
+}
+
+#[test]
+fn test_split() {
+    let (data, starts) = make_accounts(100);
+    for chunk_count in 1..10 {
+        let boundaries = split(&data, chunk_count);
+        assert_eq!(boundaries[0], 0);
+        assert_eq!(*boundaries.last().unwrap(), data.len());
+        for boundary in &boundaries[..(boundaries.len() - 1)] {
+            assert!(starts.contains(boundary));
+        }
+    }
+    assert_eq!(split(&[], 4), vec![0]);
+}
+
+#[test]
+fn test_map_chunks() {
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+    use std::io::Write;
+
+    let (data, _) = make_accounts(1000);
+    let path = temp_dir().join(format!("rusty-tank-test-map-chunks-{}", ::std::process::id()));
+    File::create(&path).unwrap().write_all(&data).unwrap();
+
+    let ids = map_chunks(&path, 4, |chunk| {
+        stats::AccountReader::new(chunk.data).map(|account| account.unwrap().id).collect::<Vec<u32>>()
+    }, |_| true).unwrap();
+    remove_file(&path).unwrap();
+
+    assert_eq!(ids.len(), 4);
+    assert_eq!(ids.concat(), (0..1000).collect::<Vec<u32>>());
+}
+
+#[test]
+fn test_map_chunks_incomplete() {
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+    use std::io::Write;
+
+    let (data, _) = make_accounts(1000);
+    let path = temp_dir().join(format!("rusty-tank-test-map-chunks-incomplete-{}", ::std::process::id()));
+    File::create(&path).unwrap().write_all(&data).unwrap();
+
+    // Pretend that the first chunk ends in the middle of an account.
+    let chunks = map_chunks(&path, 4, |chunk| (chunk.offset, chunk.data.len()), |&(offset, length)| {
+        offset + length > data.len() / 3
+    }).unwrap();
+    remove_file(&path).unwrap();
+
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks[0].0, 0);
+    for i in 1..chunks.len() {
+        assert_eq!(chunks[i - 1].0 + chunks[i - 1].1, chunks[i].0);
+    }
+    assert_eq!(chunks[2].0 + chunks[2].1, data.len());
+}
@@ -0,0 +1,264 @@
+//! Statistics dump profiling.
+//!
+//! Usage: `profile <input> [--min-battles=N] [--encyclopedia=PATH] [--skip-corrupt] [--json]`.
+
+extern crate flate2;
+extern crate rustc_serialize;
+extern crate zstd;
+
+mod encyclopedia;
+mod input;
+mod protobuf;
+mod stats;
+
+use std::collections::BTreeMap;
+
+use stats::AccountSource;
+
+/// Default minimum battles count.
+const MIN_BATTLES: u32 = 10;
+
+/// Histogram with power-of-two buckets.
+///
+/// Bucket 0 contains zero, bucket `k` contains values from `2^(k - 1)` to `2^k - 1`.
+struct Histogram {
+    counts: Vec<usize>,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram { counts: Vec::new() }
+    }
+
+    /// Adds value to the histogram.
+    pub fn add(&mut self, value: u64) {
+        let bucket = (64 - value.leading_zeros()) as usize;
+        if self.counts.len() <= bucket {
+            self.counts.resize(bucket + 1, 0);
+        }
+        self.counts[bucket] += 1;
+    }
+
+    /// Gets value range of the bucket.
+    pub fn get_range(bucket: usize) -> (u64, u64) {
+        if bucket == 0 { (0, 0) } else { (1 << (bucket - 1), (1 << bucket) - 1) }
+    }
+
+    /// Gets total count.
+    pub fn total(&self) -> usize {
+        self.counts.iter().fold(0, |acc, &count| acc + count)
+    }
+}
+
+/// Statistics dump profile.
+struct Profile {
+    account_count: usize,
+    corrupt_account_count: usize,
+    record_count: usize,
+    tanks_per_account: Histogram,
+    battles_per_tank: Histogram,
+    /// Records with less than the minimum battles count.
+    min_battles_record_count: usize,
+    /// Accounts without any records left after the minimum battles filter.
+    min_battles_account_count: usize,
+    /// Records with wins greater than battles.
+    invalid_wins_record_count: usize,
+    /// Accounts without any records left only because of the records with wins greater than battles.
+    invalid_wins_account_count: usize,
+    /// Maps unknown tank ID into its record count.
+    unknown_tanks: BTreeMap<u16, usize>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile {
+            account_count: 0,
+            corrupt_account_count: 0,
+            record_count: 0,
+            tanks_per_account: Histogram::new(),
+            battles_per_tank: Histogram::new(),
+            min_battles_record_count: 0,
+            min_battles_account_count: 0,
+            invalid_wins_record_count: 0,
+            invalid_wins_account_count: 0,
+            unknown_tanks: BTreeMap::new(),
+        }
+    }
+
+    /// Adds account to the profile.
+    ///
+    /// Filters are applied in the same order as in `helpers::read_stats`.
+    pub fn add(&mut self, account: &stats::Account, min_battles: u32, encyclopedia: &encyclopedia::Encyclopedia) {
+        let mut min_battles_count = 0;
+        let mut rating_count = 0;
+
+        self.account_count += 1;
+        self.record_count += account.tanks.len();
+        self.tanks_per_account.add(account.tanks.len() as u64);
+
+        for tank in &account.tanks {
+            self.battles_per_tank.add(tank.battles as u64);
+            if !encyclopedia.contains(tank.id) {
+                *self.unknown_tanks.entry(tank.id).or_insert(0) += 1;
+            }
+            if tank.battles < min_battles {
+                self.min_battles_record_count += 1;
+                continue;
+            }
+            min_battles_count += 1;
+            if tank.wins > tank.battles {
+                self.invalid_wins_record_count += 1;
+                continue;
+            }
+            rating_count += 1;
+        }
+
+        if min_battles_count == 0 {
+            self.min_battles_account_count += 1;
+        } else if rating_count == 0 {
+            self.invalid_wins_account_count += 1;
+        }
+    }
+
+    /// Prints human-readable report.
+    pub fn print(&self) {
+        println!("Accounts: {}.", self.account_count);
+        println!("Corrupt accounts: {}.", self.corrupt_account_count);
+        println!("Records: {}.", self.record_count);
+        println!("Records below minimum battles: {}.", self.min_battles_record_count);
+        println!("Records with wins > battles: {}.", self.invalid_wins_record_count);
+        println!("Accounts without ratings: {}.", self.min_battles_account_count);
+        println!("Accounts without ratings due to wins > battles: {}.", self.invalid_wins_account_count);
+        println!("Unknown tanks: {}.", self.unknown_tanks.len());
+        for (tank_id, count) in &self.unknown_tanks {
+            println!("  #{}: {} records", tank_id, count);
+        }
+        println!("Tanks per account:");
+        println!("------------------");
+        print_histogram(&self.tanks_per_account);
+        println!("Battles per tank:");
+        println!("-----------------");
+        print_histogram(&self.battles_per_tank);
+    }
+
+    /// Formats the profile as JSON.
+    pub fn to_json(&self) -> String {
+        let unknown_tanks: Vec<String> = self.unknown_tanks.iter().map(
+            |(tank_id, count)| format!("{{\"tank_id\": {}, \"records\": {}}}", tank_id, count)).collect();
+        format!(
+            concat!(
+                "{{\"accounts\": {}, \"corrupt_accounts\": {}, \"records\": {}, ",
+                "\"min_battles_records\": {}, \"invalid_wins_records\": {}, \"min_battles_accounts\": {}, ",
+                "\"invalid_wins_accounts\": {}, ",
+                "\"unknown_tanks\": [{}], \"tanks_per_account\": {}, \"battles_per_tank\": {}}}"
+            ),
+            self.account_count, self.corrupt_account_count, self.record_count,
+            self.min_battles_record_count, self.invalid_wins_record_count, self.min_battles_account_count,
+            self.invalid_wins_account_count,
+            unknown_tanks.join(", "), histogram_to_json(&self.tanks_per_account),
+            histogram_to_json(&self.battles_per_tank),
+        )
+    }
+}
+
+/// Prints histogram.
+fn print_histogram(histogram: &Histogram) {
+    let total = histogram.total() as f64;
+
+    for (bucket, &count) in histogram.counts.iter().enumerate() {
+        if count != 0 {
+            let (min, max) = Histogram::get_range(bucket);
+            let frequency = count as f64 / total;
+            let bar = std::iter::repeat("x").take((100.0 * frequency) as usize).collect::<String>();
+            println!("  {0:>10}-{1:<10}: {2:10} {3:6.2}% {4}", min, max, count, 100.0 * frequency, bar);
+        }
+    }
+}
+
+/// Formats histogram as JSON array of buckets.
+fn histogram_to_json(histogram: &Histogram) -> String {
+    let buckets: Vec<String> = histogram.counts.iter().enumerate().filter(|&(_, &count)| count != 0).map(|(bucket, count)| {
+        let (min, max) = Histogram::get_range(bucket);
+        format!("{{\"min\": {}, \"max\": {}, \"count\": {}}}", min, max, count)
+    }).collect();
+    format!("[{}]", buckets.join(", "))
+}
+
+/// Gets the value of `--name=value` option.
+fn get_option(name: &str) -> Option<String> {
+    use std::env::args;
+
+    let prefix = format!("--{}=", name);
+    args().skip(2).find(|arg| arg.starts_with(&prefix)).map(|arg| arg[prefix.len()..].to_string())
+}
+
+/// Gets whether the flag is passed after the input path.
+fn has_flag(flag: &str) -> bool {
+    use std::env::args;
+
+    args().skip(2).any(|arg| arg == flag)
+}
+
+#[allow(dead_code)]
+fn main() {
+    use std::env::args;
+    use std::path::Path;
+
+    let min_battles = get_option("min-battles").map_or(MIN_BATTLES, |value| value.parse().unwrap());
+    let skip_corrupt = has_flag("--skip-corrupt");
+    let encyclopedia = get_option("encyclopedia").map_or_else(
+        encyclopedia::Encyclopedia::new, |path| encyclopedia::Encyclopedia::load(&Path::new(&path)).unwrap());
+    let input = input::Input::open(&Path::new(&args().nth(1).unwrap())).unwrap();
+    let mut reader = stats::AccountReader::new(input);
+    let mut profile = Profile::new();
+
+    loop {
+        match reader.read_account() {
+            Ok(Some(account)) => profile.add(&account, min_battles, &encyclopedia),
+            Ok(None) => break,
+            Err(ref error) if skip_corrupt && error.is_recoverable() => profile.corrupt_account_count += 1,
+            Err(error) => panic!("failed to read statistics: {}", error),
+        }
+    }
+
+    if has_flag("--json") {
+        println!("{}", profile.to_json());
+    } else {
+        println!("Read {:.1} MiB.", reader.offset() as f64 / (1024.0 * 1024.0));
+        profile.print();
+    }
+}
+
+#[test]
+fn test_histogram() {
+    let mut histogram = Histogram::new();
+    for &value in &[0, 1, 2, 3, 4, 7, 8, 1000] {
+        histogram.add(value);
+    }
+    assert_eq!(histogram.counts, vec![1, 1, 2, 2, 1, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(histogram.total(), 8);
+    assert_eq!(Histogram::get_range(0), (0, 0));
+    assert_eq!(Histogram::get_range(1), (1, 1));
+    assert_eq!(Histogram::get_range(4), (8, 15));
+}
+
+#[test]
+fn test_profile() {
+    let encyclopedia = encyclopedia::Encyclopedia::new();
+    let mut profile = Profile::new();
+    profile.add(&stats::Account { id: 1, tanks: vec![stats::Tank::new(1, 5, 2), stats::Tank::new(33, 20, 21)] }, 10, &encyclopedia);
+    profile.add(&stats::Account { id: 2, tanks: vec![stats::Tank::new(1, 50, 25), stats::Tank::new(2, 20, 10)] }, 10, &encyclopedia);
+
+    assert_eq!(profile.account_count, 2);
+    assert_eq!(profile.record_count, 4);
+    assert_eq!(profile.min_battles_record_count, 1);
+    assert_eq!(profile.invalid_wins_record_count, 1);
+    assert_eq!(profile.min_battles_account_count, 0);
+    assert_eq!(profile.invalid_wins_account_count, 1);
+    assert_eq!(profile.unknown_tanks.get(&2), Some(&1));
+    assert!(profile.to_json().starts_with("{\"accounts\": 2, \"corrupt_accounts\": 0, \"records\": 4, "));
+
+    profile.add(&stats::Account { id: 3, tanks: vec![stats::Tank::new(1, 5, 6)] }, 10, &encyclopedia);
+    assert_eq!(profile.min_battles_account_count, 1);
+    assert_eq!(profile.invalid_wins_account_count, 1);
+}
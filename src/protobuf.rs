@@ -1,31 +1,437 @@
 //! Protocol Buffers format.
-use std::io::Read;
+use std::error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Maximum UVarint length in bytes.
+pub const MAX_UVARINT_LENGTH: usize = 10;
+
+/// Wire type of UVarint values.
+pub const WIRE_VARINT: u8 = 0;
+/// Wire type of 64-bit fixed-size values.
+pub const WIRE_FIXED64: u8 = 1;
+/// Wire type of length-delimited values.
+pub const WIRE_LENGTH_DELIMITED: u8 = 2;
+/// Wire type of 32-bit fixed-size values.
+pub const WIRE_FIXED32: u8 = 5;
+
+/// Protocol Buffers decoding error.
+#[derive(Debug)]
+pub enum Error {
+    /// Input ended in the middle of a value.
+    UnexpectedEof,
+    /// UVarint is longer than `MAX_UVARINT_LENGTH` bytes.
+    Overflow,
+    /// Field key contains an unknown wire type.
+    InvalidWireType(u8),
+    /// Underlying I/O error.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnexpectedEof => write!(f, "unexpected end of input"),
+            Error::Overflow => write!(f, "varint is longer than {} bytes", MAX_UVARINT_LENGTH),
+            Error::InvalidWireType(wire_type) => write!(f, "invalid wire type {}", wire_type),
+            Error::Io(ref error) => write!(f, "I/O error: {}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::UnexpectedEof => "unexpected end of input",
+            Error::Overflow => "varint overflow",
+            Error::InvalidWireType(_) => "invalid wire type",
+            Error::Io(_) => "I/O error",
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
 
 /// Reads next UVarint.
-pub fn read_uvarint<R: Read>(input: &mut R) -> Option<u64> {
+///
+/// Returns `None` if the input ends before the first byte.
+pub fn read_uvarint<R: Read>(input: &mut R) -> Result<Option<u64>, Error> {
     let mut value = 0;
     let mut shift: usize = 0;
+    let mut length = 0;
 
     loop {
         let mut buffer = [0u8; 1];
-        if input.read(&mut buffer).unwrap() == 0 {
-            return None;
+        match input.read(&mut buffer) {
+            Ok(0) => return if length == 0 { Ok(None) } else { Err(Error::UnexpectedEof) },
+            Ok(_) => (),
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(Error::Io(error)),
+        }
+        length += 1;
+        if length > MAX_UVARINT_LENGTH {
+            return Err(Error::Overflow);
         }
         value |= ((buffer[0] & 0x7F) as u64) << shift;
         if buffer[0] & 0x80 == 0 {
-            return Some(value);
+            return Ok(Some(value));
         }
         shift += 7;
     }
 }
 
+/// Decodes UVarint from the beginning of the buffer.
+///
+/// Returns the value and its length in bytes, or `None` if the buffer ends in the middle of the value.
+pub fn decode_uvarint(buffer: &[u8]) -> Result<Option<(u64, usize)>, Error> {
+    let mut value = 0;
+    let mut shift: usize = 0;
+
+    for (index, &byte) in buffer.iter().enumerate() {
+        if index == MAX_UVARINT_LENGTH {
+            return Err(Error::Overflow);
+        }
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, index + 1)));
+        }
+        shift += 7;
+    }
+
+    Ok(None)
+}
+
+/// Encodes signed value with ZigZag encoding.
+pub fn encode_zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Decodes ZigZag-encoded signed value.
+pub fn decode_zigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Wire format decoder over a byte buffer.
+pub struct Decoder<'a> {
+    buffer: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Decoder { buffer: buffer, position: 0 }
+    }
+
+    /// Gets the number of bytes decoded so far.
+    #[allow(dead_code)]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Gets whether the whole buffer is decoded.
+    pub fn is_empty(&self) -> bool {
+        self.position == self.buffer.len()
+    }
+
+    /// Reads field key.
+    ///
+    /// Returns field number and wire type, or `None` at the end of the buffer.
+    pub fn read_key(&mut self) -> Result<Option<(u32, u8)>, Error> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let key = try!(self.read_uvarint());
+        let wire_type = (key & 0x07) as u8;
+        match wire_type {
+            WIRE_VARINT | WIRE_FIXED64 | WIRE_LENGTH_DELIMITED | WIRE_FIXED32 => Ok(Some(((key >> 3) as u32, wire_type))),
+            _ => Err(Error::InvalidWireType(wire_type)),
+        }
+    }
+
+    /// Reads UVarint.
+    pub fn read_uvarint(&mut self) -> Result<u64, Error> {
+        match try!(decode_uvarint(&self.buffer[self.position..])) {
+            Some((value, length)) => {
+                self.position += length;
+                Ok(value)
+            }
+            None => Err(Error::UnexpectedEof),
+        }
+    }
+
+    /// Reads ZigZag-encoded signed varint.
+    #[allow(dead_code)]
+    pub fn read_svarint(&mut self) -> Result<i64, Error> {
+        self.read_uvarint().map(decode_zigzag)
+    }
+
+    /// Reads little-endian 32-bit fixed-size value.
+    #[allow(dead_code)]
+    pub fn read_fixed32(&mut self) -> Result<u32, Error> {
+        let bytes = try!(self.read_slice(4));
+        Ok((0..4).fold(0, |value, i| value | (bytes[i] as u32) << (8 * i)))
+    }
+
+    /// Reads little-endian 64-bit fixed-size value.
+    #[allow(dead_code)]
+    pub fn read_fixed64(&mut self) -> Result<u64, Error> {
+        let bytes = try!(self.read_slice(8));
+        Ok((0..8).fold(0, |value, i| value | (bytes[i] as u64) << (8 * i)))
+    }
+
+    /// Reads length-delimited value.
+    #[allow(dead_code)]
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], Error> {
+        let length = try!(self.read_uvarint());
+        if length > (self.buffer.len() - self.position) as u64 {
+            return Err(Error::UnexpectedEof);
+        }
+        self.read_slice(length as usize)
+    }
+
+    /// Skips value of the given wire type.
+    pub fn skip(&mut self, wire_type: u8) -> Result<(), Error> {
+        match wire_type {
+            WIRE_VARINT => self.read_uvarint().map(|_| ()),
+            WIRE_FIXED64 => self.read_slice(8).map(|_| ()),
+            WIRE_LENGTH_DELIMITED => self.read_bytes().map(|_| ()),
+            WIRE_FIXED32 => self.read_slice(4).map(|_| ()),
+            _ => Err(Error::InvalidWireType(wire_type)),
+        }
+    }
+
+    fn read_slice(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if self.buffer.len() - self.position < length {
+            return Err(Error::UnexpectedEof);
+        }
+        let slice = &self.buffer[self.position..(self.position + length)];
+        self.position += length;
+        Ok(slice)
+    }
+}
+
+/// Writes field key.
+#[allow(dead_code)]
+pub fn write_key<W: Write>(output: &mut W, field_number: u32, wire_type: u8) -> io::Result<()> {
+    write_uvarint(output, ((field_number as u64) << 3) | wire_type as u64)
+}
+
+/// Writes ZigZag-encoded signed varint.
+#[allow(dead_code)]
+pub fn write_svarint<W: Write>(output: &mut W, value: i64) -> io::Result<()> {
+    write_uvarint(output, encode_zigzag(value))
+}
+
+/// Writes little-endian 32-bit fixed-size value.
+#[allow(dead_code)]
+pub fn write_fixed32<W: Write>(output: &mut W, value: u32) -> io::Result<()> {
+    let mut buffer = [0u8; 4];
+    for i in 0..4 {
+        buffer[i] = (value >> (8 * i)) as u8;
+    }
+    output.write_all(&buffer)
+}
+
+/// Writes little-endian 64-bit fixed-size value.
+#[allow(dead_code)]
+pub fn write_fixed64<W: Write>(output: &mut W, value: u64) -> io::Result<()> {
+    let mut buffer = [0u8; 8];
+    for i in 0..8 {
+        buffer[i] = (value >> (8 * i)) as u8;
+    }
+    output.write_all(&buffer)
+}
+
+/// Writes length-delimited value.
+#[allow(dead_code)]
+pub fn write_bytes<W: Write>(output: &mut W, value: &[u8]) -> io::Result<()> {
+    try!(write_uvarint(output, value.len() as u64));
+    output.write_all(value)
+}
+
+/// Writes UVarint.
+#[allow(dead_code)]
+pub fn write_uvarint<W: Write>(output: &mut W, value: u64) -> io::Result<()> {
+    let mut buffer = [0u8; MAX_UVARINT_LENGTH];
+    let mut length = 0;
+    let mut value = value;
+
+    loop {
+        buffer[length] = (value & 0x7F) as u8;
+        length += 1;
+        value >>= 7;
+        if value == 0 {
+            break;
+        }
+        buffer[length - 1] |= 0x80;
+    }
+
+    output.write_all(&buffer[..length])
+}
+
 #[test]
 fn test_read_uvarint() {
     use std::io::Cursor;
 
-    assert_eq!(read_uvarint(&mut Cursor::new(vec![0x80])), None);
-    assert_eq!(read_uvarint(&mut Cursor::new(vec![0x00])).unwrap(), 0);
-    assert_eq!(read_uvarint(&mut Cursor::new(vec![0x03])).unwrap(), 3);
-    assert_eq!(read_uvarint(&mut Cursor::new(vec![0x8E, 0x02])).unwrap(), 270);
-    assert_eq!(read_uvarint(&mut Cursor::new(vec![0x9E, 0xA7, 0x05])).unwrap(), 86942);
+    assert_eq!(read_uvarint(&mut Cursor::new(vec![])).unwrap(), None);
+    assert_eq!(read_uvarint(&mut Cursor::new(vec![0x00])).unwrap(), Some(0));
+    assert_eq!(read_uvarint(&mut Cursor::new(vec![0x03])).unwrap(), Some(3));
+    assert_eq!(read_uvarint(&mut Cursor::new(vec![0x8E, 0x02])).unwrap(), Some(270));
+    assert_eq!(read_uvarint(&mut Cursor::new(vec![0x9E, 0xA7, 0x05])).unwrap(), Some(86942));
+}
+
+#[test]
+fn test_read_uvarint_truncated() {
+    use std::io::Cursor;
+
+    match read_uvarint(&mut Cursor::new(vec![0x80])) {
+        Err(Error::UnexpectedEof) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_read_uvarint_overflow() {
+    use std::io::Cursor;
+
+    match read_uvarint(&mut Cursor::new(vec![0xFF; 11])) {
+        Err(Error::Overflow) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_decode_uvarint() {
+    assert_eq!(decode_uvarint(&[]).unwrap(), None);
+    assert_eq!(decode_uvarint(&[0x80]).unwrap(), None);
+    assert_eq!(decode_uvarint(&[0x03, 0xFF]).unwrap(), Some((3, 1)));
+    assert_eq!(decode_uvarint(&[0x9E, 0xA7, 0x05, 0x00]).unwrap(), Some((86942, 3)));
+    match decode_uvarint(&[0xFF; 11]) {
+        Err(Error::Overflow) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_zigzag() {
+    for &(value, encoded) in &[(0, 0), (-1, 1), (1, 2), (-2, 3), (2147483647, 4294967294), (-2147483648, 4294967295)] {
+        assert_eq!(encode_zigzag(value), encoded);
+        assert_eq!(decode_zigzag(encoded), value);
+    }
+    assert_eq!(decode_zigzag(encode_zigzag(::std::i64::MIN)), ::std::i64::MIN);
+    assert_eq!(decode_zigzag(encode_zigzag(::std::i64::MAX)), ::std::i64::MAX);
+}
+
+#[test]
+fn test_decoder() {
+    let buffer = [
+        0x08, 0x96, 0x01,
+        0x15, 0x01, 0x02, 0x03, 0x04,
+        0x19, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+        0x22, 0x03, 0x61, 0x62, 0x63,
+        0x28, 0x03,
+    ];
+    let mut decoder = Decoder::new(&buffer);
+    assert_eq!(decoder.read_key().unwrap(), Some((1, WIRE_VARINT)));
+    assert_eq!(decoder.read_uvarint().unwrap(), 150);
+    assert_eq!(decoder.read_key().unwrap(), Some((2, WIRE_FIXED32)));
+    assert_eq!(decoder.read_fixed32().unwrap(), 0x04030201);
+    assert_eq!(decoder.read_key().unwrap(), Some((3, WIRE_FIXED64)));
+    assert_eq!(decoder.read_fixed64().unwrap(), 0x8000000000000001);
+    assert_eq!(decoder.read_key().unwrap(), Some((4, WIRE_LENGTH_DELIMITED)));
+    assert_eq!(decoder.read_bytes().unwrap(), b"abc");
+    assert_eq!(decoder.read_key().unwrap(), Some((5, WIRE_VARINT)));
+    assert_eq!(decoder.read_svarint().unwrap(), -2);
+    assert_eq!(decoder.read_key().unwrap(), None);
+    assert_eq!(decoder.position(), buffer.len());
+}
+
+#[test]
+fn test_decoder_skip() {
+    let buffer = [0x15, 0x01, 0x02, 0x03, 0x04, 0x22, 0x01, 0x00, 0x08, 0x01];
+    let mut decoder = Decoder::new(&buffer);
+    let (_, wire_type) = decoder.read_key().unwrap().unwrap();
+    decoder.skip(wire_type).unwrap();
+    let (_, wire_type) = decoder.read_key().unwrap().unwrap();
+    decoder.skip(wire_type).unwrap();
+    assert_eq!(decoder.read_key().unwrap(), Some((1, WIRE_VARINT)));
+    assert_eq!(decoder.read_uvarint().unwrap(), 1);
+    assert!(decoder.is_empty());
+}
+
+#[test]
+fn test_decoder_errors() {
+    match Decoder::new(&[0x0B]).read_key() {
+        Err(Error::InvalidWireType(3)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match Decoder::new(&[0x01, 0x02]).read_fixed32() {
+        Err(Error::UnexpectedEof) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match Decoder::new(&[0x05, 0x01]).read_bytes() {
+        Err(Error::UnexpectedEof) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_encoder_decoder() {
+    let mut output = Vec::new();
+    write_key(&mut output, 1, WIRE_VARINT).unwrap();
+    write_svarint(&mut output, -150).unwrap();
+    write_key(&mut output, 2, WIRE_FIXED32).unwrap();
+    write_fixed32(&mut output, 0xDEADBEEF).unwrap();
+    write_key(&mut output, 3, WIRE_FIXED64).unwrap();
+    write_fixed64(&mut output, ::std::u64::MAX - 1).unwrap();
+    write_key(&mut output, 4, WIRE_LENGTH_DELIMITED).unwrap();
+    write_bytes(&mut output, b"tank").unwrap();
+
+    let mut decoder = Decoder::new(&output);
+    assert_eq!(decoder.read_key().unwrap(), Some((1, WIRE_VARINT)));
+    assert_eq!(decoder.read_svarint().unwrap(), -150);
+    assert_eq!(decoder.read_key().unwrap(), Some((2, WIRE_FIXED32)));
+    assert_eq!(decoder.read_fixed32().unwrap(), 0xDEADBEEF);
+    assert_eq!(decoder.read_key().unwrap(), Some((3, WIRE_FIXED64)));
+    assert_eq!(decoder.read_fixed64().unwrap(), ::std::u64::MAX - 1);
+    assert_eq!(decoder.read_key().unwrap(), Some((4, WIRE_LENGTH_DELIMITED)));
+    assert_eq!(decoder.read_bytes().unwrap(), b"tank");
+    assert!(decoder.is_empty());
+}
+
+#[test]
+fn test_write_key() {
+    let mut output = Vec::new();
+    write_key(&mut output, 20, WIRE_FIXED32).unwrap();
+    assert_eq!(output, vec![0xA5, 0x01]);
+}
+
+#[test]
+fn test_write_uvarint() {
+    fn write(value: u64) -> Vec<u8> {
+        let mut output = Vec::new();
+        write_uvarint(&mut output, value).unwrap();
+        output
+    }
+
+    assert_eq!(write(0), vec![0x00]);
+    assert_eq!(write(3), vec![0x03]);
+    assert_eq!(write(270), vec![0x8E, 0x02]);
+    assert_eq!(write(86942), vec![0x9E, 0xA7, 0x05]);
+    assert_eq!(write(::std::u64::MAX).len(), MAX_UVARINT_LENGTH);
+}
+
+#[test]
+fn test_write_read_uvarint() {
+    use std::io::Cursor;
+
+    for &value in &[0, 1, 127, 128, 16383, 16384, 86942, ::std::u32::MAX as u64, ::std::u64::MAX] {
+        let mut output = Vec::new();
+        write_uvarint(&mut output, value).unwrap();
+        assert_eq!(read_uvarint(&mut Cursor::new(output)).unwrap(), Some(value));
+    }
 }
@@ -1,13 +1,25 @@
 //! Slope One for Tankopoisk.
 
+extern crate flate2;
+extern crate memmap;
 extern crate rand;
+extern crate rustc_serialize;
 extern crate time;
+extern crate zstd;
 
+mod accounts;
+mod checksum;
 mod csr;
 mod encyclopedia;
+mod evaluation;
+mod filter;
 mod helpers;
+mod input;
+mod parallel;
 mod protobuf;
+mod split;
 mod stats;
+mod text;
 
 const MIN_BATTLES: u32 = 10;
 
@@ -81,17 +93,31 @@ impl helpers::AbstractModel for Model {
 
 #[allow(dead_code)]
 fn main() {
-    let (encyclopedia, train_matrix, test_matrix) = helpers::get_stats(MIN_BATTLES, helpers::identity);
+    let (encyclopedia, train_matrix, test_matrix, accounts) = helpers::get_stats(MIN_BATTLES, helpers::identity);
+    if helpers::run_cross_validation(&train_matrix, &test_matrix, |train_matrix| {
+        let mut model = Model::new(encyclopedia.len());
+        model.train(train_matrix);
+        model
+    }, helpers::identity) {
+        return;
+    }
     println!("Training.");
     let mut model = Model::new(encyclopedia.len());
     model.train(&train_matrix);
     println!("Evaluating.");
-    let train_error = helpers::evaluate(&model, &train_matrix, &train_matrix, helpers::identity);
-    println!("Train error: {0:.6}.", train_error);
-    let test_error = helpers::evaluate(&model, &train_matrix, &test_matrix, helpers::identity);
-    println!("Test error: {0:.6}.", test_error);
+    let train_report = helpers::evaluate(&model, &train_matrix, &train_matrix, helpers::identity);
+    println!("Train error: {}.", train_report);
+    let test_report = helpers::evaluate(&model, &train_matrix, &test_matrix, helpers::identity);
+    println!("Test error: {}.", test_report);
     let error_distribution = helpers::evaluate_error_distribution(&model, &train_matrix, &test_matrix, helpers::identity);
     println!("Test error distribution:");
     println!("------------------------");
     helpers::print_error_distribution(error_distribution);
+    println!("Test error by tank:");
+    println!("-------------------");
+    test_report.print_columns(&encyclopedia);
+    println!("Test error by tier:");
+    println!("-------------------");
+    test_report.print_tiers(&encyclopedia);
+    helpers::print_recommendations(&model, &train_matrix, &test_matrix, &encyclopedia, &accounts);
 }
@@ -0,0 +1,209 @@
+//! Train/test split strategies.
+//!
+//! Strategy is passed as `name:parameter`, e.g. `random:0.05`, `leave-k-out:1`, `account:0.1`, `tank:0.1` or `battles:0.05`.
+
+use rand::{Rng, ThreadRng, thread_rng};
+
+/// Default ratio of the test ratings.
+pub const DEFAULT_RATIO: f64 = 0.05;
+
+/// Number of battles buckets. Bucket `k` contains battles from `2^(k - 1)` to `2^k - 1`.
+const BUCKET_COUNT: usize = 33;
+
+/// Defines which ratings go to the test matrix.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    /// Each rating goes to the test matrix with the probability.
+    Random(f64),
+    /// The number of random ratings of each account goes to the test matrix.
+    /// Accounts without more ratings than that are used only for training.
+    LeaveKOut(usize),
+    /// All ratings of an account go to the test matrix with the probability. Tests cold-start accounts.
+    AccountHoldout(f64),
+    /// All ratings of a tank go to the test matrix with the probability. Tests cold-start tanks.
+    ///
+    /// Tanks are picked by the seeded hash of their IDs, so that every thread picks the same ones.
+    TankHoldout { ratio: f64, seed: u64 },
+    /// The ratio of the ratings goes to the test matrix exactly within each battles bucket.
+    Battles(f64),
+}
+
+impl Strategy {
+    /// Parses strategy. The parameter may be omitted to use the default one.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut parts = value.splitn(2, ':');
+        let name = parts.next().unwrap();
+        let parameter = parts.next();
+        match name {
+            "random" => parse_ratio(parameter).map(Strategy::Random),
+            "leave-k-out" => match parameter {
+                Some(count) => count.parse().map(Strategy::LeaveKOut).map_err(|_| format!("invalid count: {:?}", count)),
+                None => Ok(Strategy::LeaveKOut(1)),
+            },
+            "account" => parse_ratio(parameter).map(Strategy::AccountHoldout),
+            "tank" => parse_ratio(parameter).map(|ratio| Strategy::TankHoldout { ratio: ratio, seed: thread_rng().gen() }),
+            "battles" => parse_ratio(parameter).map(Strategy::Battles),
+            _ => Err(format!("unknown split strategy: {:?}", name)),
+        }
+    }
+}
+
+/// Parses ratio from 0 to 1. Returns the default one if it is omitted.
+fn parse_ratio(value: Option<&str>) -> Result<f64, String> {
+    let ratio = match value {
+        Some(value) => try!(value.parse().map_err(|_| format!("invalid ratio: {:?}", value))),
+        None => DEFAULT_RATIO,
+    };
+    if ratio >= 0.0 && ratio <= 1.0 { Ok(ratio) } else { Err(format!("ratio must be from 0 to 1: {}", ratio)) }
+}
+
+/// Rating to split.
+#[derive(Clone, Copy, Debug)]
+pub struct Rating {
+    pub tank_id: u16,
+    pub battles: u32,
+}
+
+/// Splits ratings of accounts one by one.
+pub struct Splitter {
+    strategy: Strategy,
+    rng: ThreadRng,
+    /// Number of ratings seen in each battles bucket.
+    bucket_counts: Vec<usize>,
+    /// Random phase of each battles bucket.
+    bucket_offsets: Vec<f64>,
+    /// Rating indices shuffled for leave-k-out.
+    indices: Vec<usize>,
+}
+
+impl Splitter {
+    pub fn new(strategy: Strategy) -> Self {
+        let mut rng = thread_rng();
+        let bucket_offsets = (0..BUCKET_COUNT).map(|_| rng.gen()).collect();
+        Splitter { strategy: strategy, rng: rng, bucket_counts: vec![0; BUCKET_COUNT], bucket_offsets: bucket_offsets, indices: Vec::new() }
+    }
+
+    /// Decides whether each rating of an account goes to the test matrix.
+    pub fn split(&mut self, ratings: &[Rating], is_test: &mut Vec<bool>) {
+        is_test.clear();
+        match self.strategy {
+            Strategy::Random(ratio) => {
+                for _ in ratings {
+                    is_test.push(self.rng.gen::<f64>() < ratio);
+                }
+            }
+            Strategy::LeaveKOut(count) => {
+                is_test.resize(ratings.len(), false);
+                if ratings.len() > count {
+                    // Partial Fisher–Yates shuffle: the first `count` indices become a random sample.
+                    self.indices.clear();
+                    self.indices.extend(0..ratings.len());
+                    for i in 0..count {
+                        let j = self.rng.gen_range(i, ratings.len());
+                        self.indices.swap(i, j);
+                        is_test[self.indices[i]] = true;
+                    }
+                }
+            }
+            Strategy::AccountHoldout(ratio) => {
+                let is_account_test = self.rng.gen::<f64>() < ratio;
+                is_test.resize(ratings.len(), is_account_test);
+            }
+            Strategy::TankHoldout { ratio, seed } => {
+                for rating in ratings {
+                    is_test.push(hash_fraction(seed, rating.tank_id) < ratio);
+                }
+            }
+            Strategy::Battles(ratio) => {
+                for rating in ratings {
+                    let bucket = (32 - rating.battles.leading_zeros()) as usize;
+                    let position = self.bucket_counts[bucket] as f64 * ratio + self.bucket_offsets[bucket];
+                    self.bucket_counts[bucket] += 1;
+                    is_test.push((position + ratio).floor() > position.floor());
+                }
+            }
+        }
+    }
+}
+
+/// Hashes the tank ID with the SplitMix64 finalizer into a fraction from 0 to 1.
+fn hash_fraction(seed: u64, tank_id: u16) -> f64 {
+    let mut hash = seed ^ tank_id as u64;
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^= hash >> 31;
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+fn make_ratings(count: usize) -> Vec<Rating> {
+    (0..count).map(|index| Rating { tank_id: index as u16, battles: 10 + index as u32 % 7 }).collect()
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(Strategy::parse("random"), Ok(Strategy::Random(DEFAULT_RATIO)));
+    assert_eq!(Strategy::parse("random:0.2"), Ok(Strategy::Random(0.2)));
+    assert_eq!(Strategy::parse("leave-k-out"), Ok(Strategy::LeaveKOut(1)));
+    assert_eq!(Strategy::parse("leave-k-out:3"), Ok(Strategy::LeaveKOut(3)));
+    assert_eq!(Strategy::parse("account:0.1"), Ok(Strategy::AccountHoldout(0.1)));
+    assert_eq!(Strategy::parse("battles"), Ok(Strategy::Battles(DEFAULT_RATIO)));
+    match Strategy::parse("tank:0.3") {
+        Ok(Strategy::TankHoldout { ratio, .. }) => assert_eq!(ratio, 0.3),
+        other => panic!("unexpected strategy: {:?}", other),
+    }
+    assert!(Strategy::parse("random:2").is_err());
+    assert!(Strategy::parse("leave-k-out:0.5").is_err());
+    assert!(Strategy::parse("magic").is_err());
+}
+
+#[test]
+fn test_leave_k_out() {
+    let mut splitter = Splitter::new(Strategy::LeaveKOut(2));
+    let mut is_test = Vec::new();
+    splitter.split(&make_ratings(5), &mut is_test);
+    assert_eq!(is_test.len(), 5);
+    assert_eq!(is_test.iter().filter(|&&is_test| is_test).count(), 2);
+    splitter.split(&make_ratings(2), &mut is_test);
+    assert_eq!(is_test, vec![false, false]);
+}
+
+#[test]
+fn test_account_holdout() {
+    let mut is_test = Vec::new();
+    Splitter::new(Strategy::AccountHoldout(1.0)).split(&make_ratings(3), &mut is_test);
+    assert_eq!(is_test, vec![true, true, true]);
+    Splitter::new(Strategy::AccountHoldout(0.0)).split(&make_ratings(3), &mut is_test);
+    assert_eq!(is_test, vec![false, false, false]);
+}
+
+#[test]
+fn test_tank_holdout() {
+    let strategy = Strategy::TankHoldout { ratio: 0.5, seed: 42 };
+    let ratings = make_ratings(1000);
+    let mut is_test = Vec::new();
+    Splitter::new(strategy).split(&ratings, &mut is_test);
+    let test_count = is_test.iter().filter(|&&is_test| is_test).count();
+    assert!(test_count > 400 && test_count < 600, "{}", test_count);
+
+    // Another splitter picks the same tanks.
+    let mut other_is_test = Vec::new();
+    Splitter::new(strategy).split(&ratings[..10], &mut other_is_test);
+    assert_eq!(&other_is_test[..], &is_test[..10]);
+}
+
+#[test]
+fn test_battles() {
+    let mut splitter = Splitter::new(Strategy::Battles(0.25));
+    let mut is_test = Vec::new();
+    let mut test_counts = [0; 2];
+    for _ in 0..100 {
+        splitter.split(&[Rating { tank_id: 1, battles: 10 }, Rating { tank_id: 2, battles: 1000 }], &mut is_test);
+        for (test_count, &is_test) in test_counts.iter_mut().zip(&is_test) {
+            if is_test {
+                *test_count += 1;
+            }
+        }
+    }
+    assert_eq!(test_counts, [25, 25]);
+}
@@ -1,13 +1,53 @@
-//! Statistics file reader.
-use std::io::Read;
+//! Statistics file reader and writer.
+use std::error;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::{u16, u32};
 
 use protobuf;
 
-#[derive(Debug)]
+/// Account header magic byte.
+///
+/// Each account starts with the magic byte followed by the format version byte.
+pub const HEADER_MAGIC: u8 = 0x3e;
+/// Legacy format: account ID, tank count, then ID, battles and wins for each tank.
+pub const FORMAT_LEGACY: u8 = 0x3e;
+/// Tagged format: account ID, tank count, then a length-delimited tagged record for each tank.
+///
+/// Unknown fields of tank records are skipped.
+pub const FORMAT_TAGGED: u8 = 0x3f;
+/// Format versions from `FORMAT_LEGACY` up to this one are reserved for future layouts.
+pub const MAX_RESERVED_FORMAT: u8 = 0x4d;
+
+/// Maximum tagged tank record length in bytes.
+const MAX_TANK_RECORD_LENGTH: usize = 1024;
+
+/// Tagged tank record field numbers.
+const FIELD_ID: u32 = 1;
+const FIELD_BATTLES: u32 = 2;
+const FIELD_WINS: u32 = 3;
+const FIELD_DAMAGE_DEALT: u32 = 4;
+const FIELD_FRAGS: u32 = 5;
+const FIELD_SPOTTED: u32 = 6;
+const FIELD_EXPERIENCE: u32 = 7;
+
+#[derive(Clone, Debug)]
 pub struct Tank {
     pub id: u16,
     pub battles: u32,
     pub wins: u32,
+    /// The following fields are only available in the tagged format.
+    pub damage_dealt: Option<u64>,
+    pub frags: Option<u32>,
+    pub spotted: Option<u32>,
+    pub experience: Option<u64>,
+}
+
+impl Tank {
+    /// Creates tank statistics without optional fields.
+    pub fn new(id: u16, battles: u32, wins: u32) -> Self {
+        Tank { id: id, battles: battles, wins: wins, damage_dealt: None, frags: None, spotted: None, experience: None }
+    }
 }
 
 #[derive(Debug)]
@@ -16,37 +56,719 @@ pub struct Account {
     pub tanks: Vec<Tank>,
 }
 
-/// Reads next account statistics.
-pub fn read_account<R: Read>(input: &mut R) -> Option<Account> {
-    if !skip_account_header(input) {
-        return None;
+/// Statistics reading error.
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    /// Byte offset where the error was detected.
+    pub offset: u64,
+    /// Index of the account being read.
+    pub account_index: usize,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// Input ended in the middle of an account.
+    TruncatedRecord,
+    /// UVarint is longer than `protobuf::MAX_UVARINT_LENGTH` bytes.
+    VarintOverflow,
+    /// Tank ID doesn't fit into `u16`.
+    TankIdOutOfRange(u64),
+    /// Named value doesn't fit into `u32`.
+    ValueOutOfRange(&'static str, u64),
+    /// Account header is neither a known nor a reserved one.
+    InvalidHeader([u8; 2]),
+    /// Account header contains a reserved format version this reader doesn't know.
+    UnsupportedFormat(u8),
+    /// Text row can't be parsed.
+    InvalidRow(String),
+    /// Tagged tank record can't be decoded.
+    MalformedRecord(String),
+    /// Underlying I/O error.
+    Io(io::Error),
+}
+
+impl Error {
+    /// Gets whether reading may continue after the error.
+    pub fn is_recoverable(&self) -> bool {
+        match self.kind {
+            ErrorKind::VarintOverflow |
+            ErrorKind::TankIdOutOfRange(_) |
+            ErrorKind::ValueOutOfRange(..) |
+            ErrorKind::InvalidHeader(_) |
+            ErrorKind::UnsupportedFormat(_) |
+            ErrorKind::InvalidRow(_) |
+            ErrorKind::MalformedRecord(_) => true,
+            ErrorKind::TruncatedRecord | ErrorKind::Io(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(match self.kind {
+            ErrorKind::TruncatedRecord => write!(f, "truncated record"),
+            ErrorKind::VarintOverflow => write!(f, "varint overflow"),
+            ErrorKind::TankIdOutOfRange(tank_id) => write!(f, "tank ID {} is out of range", tank_id),
+            ErrorKind::ValueOutOfRange(name, value) => write!(f, "{} {} is out of range", name, value),
+            ErrorKind::InvalidHeader(header) => write!(f, "invalid header {:02x} {:02x}", header[0], header[1]),
+            ErrorKind::UnsupportedFormat(format) => write!(f, "unsupported format {:02x}", format),
+            ErrorKind::InvalidRow(ref message) => write!(f, "invalid row: {}", message),
+            ErrorKind::MalformedRecord(ref message) => write!(f, "malformed record: {}", message),
+            ErrorKind::Io(ref error) => write!(f, "I/O error: {}", error),
+        });
+        write!(f, " at byte {} (account #{})", self.offset, self.account_index)
     }
-    let account_id = protobuf::read_uvarint(input).unwrap();
-    let tank_count = protobuf::read_uvarint(input).unwrap();
-    let mut tanks = Vec::new();
-    for _ in 0..tank_count {
-        let tank_id = protobuf::read_uvarint(input).unwrap();
-        let battles = protobuf::read_uvarint(input).unwrap();
-        let wins = protobuf::read_uvarint(input).unwrap();
-        tanks.push(Tank { id: tank_id as u16, battles: battles as u32, wins: wins as u32 });
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match self.kind {
+            ErrorKind::TruncatedRecord => "truncated record",
+            ErrorKind::VarintOverflow => "varint overflow",
+            ErrorKind::TankIdOutOfRange(_) => "tank ID is out of range",
+            ErrorKind::ValueOutOfRange(..) => "value is out of range",
+            ErrorKind::InvalidHeader(_) => "invalid header",
+            ErrorKind::UnsupportedFormat(_) => "unsupported format",
+            ErrorKind::InvalidRow(_) => "invalid row",
+            ErrorKind::MalformedRecord(_) => "malformed record",
+            ErrorKind::Io(_) => "I/O error",
+        }
+    }
+}
+
+/// Source of account statistics.
+pub trait AccountSource {
+    /// Reads next account statistics into the existing account reusing its tanks allocation.
+    ///
+    /// Returns `false` at the end of input.
+    fn read_into(&mut self, account: &mut Account) -> Result<bool, Error>;
+
+    /// Gets the number of bytes consumed so far.
+    fn offset(&self) -> u64;
+}
+
+/// Streaming account statistics reader.
+///
+/// Decodes values directly out of the input buffer and keeps track of
+/// the byte offset and the account index to report errors.
+/// After an error the next read skips input up to the next valid header.
+pub struct AccountReader<R> {
+    input: R,
+    offset: u64,
+    account_index: usize,
+    /// Whether the input is positioned at an account header.
+    is_synchronized: bool,
+    /// Last consumed byte. It may be the header magic that an invalid record has run into.
+    last_byte: Option<u8>,
+    /// Whether an unrecoverable error has been returned by the iterator.
+    is_finished: bool,
+    /// Tagged tank record buffer.
+    record: Vec<u8>,
+}
+
+impl<R: BufRead> AccountReader<R> {
+    pub fn new(input: R) -> Self {
+        AccountReader {
+            input: input,
+            offset: 0,
+            account_index: 0,
+            is_synchronized: true,
+            last_byte: None,
+            is_finished: false,
+            record: Vec::new(),
+        }
+    }
+
+    /// Reads next account statistics.
+    ///
+    /// Returns `None` at the end of input.
+    pub fn read_account(&mut self) -> Result<Option<Account>, Error> {
+        let mut account = Account { id: 0, tanks: Vec::new() };
+        Ok(if try!(self.read_into(&mut account)) { Some(account) } else { None })
+    }
+
+    fn read_next_account(&mut self, account: &mut Account) -> Result<bool, Error> {
+        let format = if self.is_synchronized { try!(self.read_header()) } else { try!(self.synchronize()) };
+        match format {
+            Some(FORMAT_LEGACY) => self.read_legacy_account(account).map(|_| true),
+            Some(FORMAT_TAGGED) => self.read_tagged_account(account).map(|_| true),
+            Some(format) => Err(self.error(ErrorKind::UnsupportedFormat(format))),
+            None => Ok(false),
+        }
+    }
+
+    /// Reads account in the legacy format.
+    fn read_legacy_account(&mut self, account: &mut Account) -> Result<(), Error> {
+        account.id = try!(self.read_u32("account ID"));
+        let tank_count = try!(self.read_uvarint());
+        for _ in 0..tank_count {
+            let tank_id = try!(self.read_uvarint());
+            if tank_id > u16::MAX as u64 {
+                return Err(self.error(ErrorKind::TankIdOutOfRange(tank_id)));
+            }
+            let battles = try!(self.read_u32("battles"));
+            let wins = try!(self.read_u32("wins"));
+            account.tanks.push(Tank::new(tank_id as u16, battles, wins));
+        }
+        Ok(())
+    }
+
+    /// Reads account in the tagged format.
+    fn read_tagged_account(&mut self, account: &mut Account) -> Result<(), Error> {
+        account.id = try!(self.read_u32("account ID"));
+        let tank_count = try!(self.read_uvarint());
+        for _ in 0..tank_count {
+            let length = try!(self.read_uvarint()) as usize;
+            if length > MAX_TANK_RECORD_LENGTH {
+                return Err(self.error(ErrorKind::MalformedRecord(format!("record length {} is too big", length))));
+            }
+            try!(self.read_record(length));
+            match decode_tank(&self.record) {
+                Ok(tank) => account.tanks.push(tank),
+                Err(kind) => return Err(self.error(kind)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads tagged record into the record buffer.
+    fn read_record(&mut self, length: usize) -> Result<(), Error> {
+        self.record.clear();
+        while self.record.len() != length {
+            let consumed = match self.input.fill_buf() {
+                Ok(buffer) if buffer.is_empty() => return Err(self.error(ErrorKind::TruncatedRecord)),
+                Ok(buffer) => {
+                    let consumed = buffer.len().min(length - self.record.len());
+                    self.record.extend(buffer[..consumed].iter().cloned());
+                    self.last_byte = Some(buffer[consumed - 1]);
+                    consumed
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => 0,
+                Err(error) => return Err(self.error(ErrorKind::Io(error))),
+            };
+            self.input.consume(consumed);
+            self.offset += consumed as u64;
+        }
+        Ok(())
+    }
+
+    /// Reads account header.
+    ///
+    /// Returns format version or `None` at the end of input.
+    fn read_header(&mut self) -> Result<Option<u8>, Error> {
+        let magic = match try!(self.read_byte()) {
+            Some(magic) => magic,
+            None => return Ok(None),
+        };
+        let format = match try!(self.read_byte()) {
+            Some(format) => format,
+            None => return Err(self.error(ErrorKind::TruncatedRecord)),
+        };
+        if magic != HEADER_MAGIC || !is_reserved_format(format) {
+            return Err(self.error(ErrorKind::InvalidHeader([magic, format])));
+        }
+        Ok(Some(format))
+    }
+
+    /// Skips input up to the next header with a supported format version.
+    ///
+    /// Returns format version or `None` at the end of input.
+    /// The search starts from the last consumed byte, so the magic is not lost if an invalid record has run into it.
+    fn synchronize(&mut self) -> Result<Option<u8>, Error> {
+        loop {
+            let previous_byte = self.last_byte;
+            let byte = match try!(self.read_byte()) {
+                Some(byte) => byte,
+                None => return Ok(None),
+            };
+            if previous_byte == Some(HEADER_MAGIC) && is_supported_format(byte) {
+                return Ok(Some(byte));
+            }
+        }
+    }
+
+    /// Reads single byte.
+    ///
+    /// Returns `None` at the end of input.
+    fn read_byte(&mut self) -> Result<Option<u8>, Error> {
+        let mut buffer = [0u8; 1];
+        loop {
+            match self.input.read(&mut buffer) {
+                Ok(0) => return Ok(None),
+                Ok(_) => {
+                    self.offset += 1;
+                    self.last_byte = Some(buffer[0]);
+                    return Ok(Some(buffer[0]));
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => (),
+                Err(error) => return Err(self.error(ErrorKind::Io(error))),
+            }
+        }
+    }
+
+    /// Reads UVarint inside an account.
+    fn read_uvarint(&mut self) -> Result<u64, Error> {
+        // Fast path: the value is entirely in the input buffer.
+        let decoded = match self.input.fill_buf() {
+            Ok(buffer) => protobuf::decode_uvarint(buffer).map(|decoded| decoded.map(|(value, length)| (value, length, buffer[length - 1]))),
+            Err(_) => Ok(None),
+        };
+        match decoded {
+            Ok(Some((value, length, last_byte))) => {
+                self.last_byte = Some(last_byte);
+                self.input.consume(length);
+                self.offset += length as u64;
+                return Ok(value);
+            }
+            Ok(None) => (),
+            Err(_) => return Err(self.error(ErrorKind::VarintOverflow)),
+        }
+        // Slow path: the value crosses the buffer boundary or reading has failed.
+        let result = {
+            let mut input = CountingRead { input: &mut self.input, count: &mut self.offset, last_byte: &mut self.last_byte };
+            protobuf::read_uvarint(&mut input)
+        };
+        match result {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) | Err(protobuf::Error::UnexpectedEof) => Err(self.error(ErrorKind::TruncatedRecord)),
+            Err(protobuf::Error::Overflow) => Err(self.error(ErrorKind::VarintOverflow)),
+            Err(protobuf::Error::Io(error)) => Err(self.error(ErrorKind::Io(error))),
+            Err(error) => Err(self.error(malformed(error))),
+        }
+    }
+
+    /// Reads UVarint which must fit into `u32`.
+    fn read_u32(&mut self, name: &'static str) -> Result<u32, Error> {
+        let value = try!(self.read_uvarint());
+        to_u32(value, name).map_err(|kind| self.error(kind))
+    }
+
+    fn error(&self, kind: ErrorKind) -> Error {
+        Error { kind: kind, offset: self.offset, account_index: self.account_index }
+    }
+}
+
+/// Counts bytes read from the underlying reader and remembers the last one.
+struct CountingRead<'a, R: 'a> {
+    input: &'a mut R,
+    count: &'a mut u64,
+    last_byte: &'a mut Option<u8>,
+}
+
+impl<'a, R: Read> Read for CountingRead<'a, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let length = try!(self.input.read(buffer));
+        *self.count += length as u64;
+        if length != 0 {
+            *self.last_byte = Some(buffer[length - 1]);
+        }
+        Ok(length)
+    }
+}
+
+impl<R: BufRead> AccountSource for AccountReader<R> {
+    fn read_into(&mut self, account: &mut Account) -> Result<bool, Error> {
+        account.tanks.clear();
+        let result = self.read_next_account(account);
+        self.is_synchronized = result.is_ok();
+        self.account_index += 1;
+        result
+    }
+
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl<R: BufRead> Iterator for AccountReader<R> {
+    type Item = Result<Account, Error>;
+
+    /// Reads next account statistics.
+    ///
+    /// Stops after the end of input or an unrecoverable error.
+    fn next(&mut self) -> Option<Result<Account, Error>> {
+        if self.is_finished {
+            return None;
+        }
+        match self.read_account() {
+            Ok(Some(account)) => Some(Ok(account)),
+            Ok(None) => {
+                self.is_finished = true;
+                None
+            }
+            Err(error) => {
+                self.is_finished = !error.is_recoverable();
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Writes account statistics in the tagged format including optional fields.
+#[allow(dead_code)]
+pub fn write_tagged_account<W: Write>(output: &mut W, account: &Account) -> io::Result<()> {
+    let mut record = Vec::new();
+
+    try!(output.write_all(&[HEADER_MAGIC, FORMAT_TAGGED]));
+    try!(protobuf::write_uvarint(output, account.id as u64));
+    try!(protobuf::write_uvarint(output, account.tanks.len() as u64));
+    for tank in &account.tanks {
+        record.clear();
+        try!(write_varint_field(&mut record, FIELD_ID, Some(tank.id as u64)));
+        try!(write_varint_field(&mut record, FIELD_BATTLES, Some(tank.battles as u64)));
+        try!(write_varint_field(&mut record, FIELD_WINS, Some(tank.wins as u64)));
+        try!(write_varint_field(&mut record, FIELD_DAMAGE_DEALT, tank.damage_dealt));
+        try!(write_varint_field(&mut record, FIELD_FRAGS, tank.frags.map(|value| value as u64)));
+        try!(write_varint_field(&mut record, FIELD_SPOTTED, tank.spotted.map(|value| value as u64)));
+        try!(write_varint_field(&mut record, FIELD_EXPERIENCE, tank.experience));
+        try!(protobuf::write_uvarint(output, record.len() as u64));
+        try!(output.write_all(&record));
+    }
+    Ok(())
+}
+
+/// Writes varint field if the value is present.
+fn write_varint_field<W: Write>(output: &mut W, field_number: u32, value: Option<u64>) -> io::Result<()> {
+    match value {
+        Some(value) => {
+            try!(protobuf::write_key(output, field_number, protobuf::WIRE_VARINT));
+            protobuf::write_uvarint(output, value)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Gets whether the format version is reserved.
+fn is_reserved_format(format: u8) -> bool {
+    format >= FORMAT_LEGACY && format <= MAX_RESERVED_FORMAT
+}
+
+/// Gets whether the format version can be read.
+pub fn is_supported_format(format: u8) -> bool {
+    format == FORMAT_LEGACY || format == FORMAT_TAGGED
+}
+
+/// Decodes tagged tank record.
+fn decode_tank(record: &[u8]) -> Result<Tank, ErrorKind> {
+    let mut id = None;
+    let mut battles = None;
+    let mut wins = None;
+    let mut tank = Tank::new(0, 0, 0);
+    let mut decoder = protobuf::Decoder::new(record);
+
+    while let Some((field_number, wire_type)) = try!(decoder.read_key().map_err(malformed)) {
+        if wire_type != protobuf::WIRE_VARINT || field_number > FIELD_EXPERIENCE {
+            // Unknown field.
+            try!(decoder.skip(wire_type).map_err(malformed));
+            continue;
+        }
+        let value = try!(decoder.read_uvarint().map_err(malformed));
+        match field_number {
+            FIELD_ID => id = Some(value),
+            FIELD_BATTLES => battles = Some(value),
+            FIELD_WINS => wins = Some(value),
+            FIELD_DAMAGE_DEALT => tank.damage_dealt = Some(value),
+            FIELD_FRAGS => tank.frags = Some(try!(to_u32(value, "frags"))),
+            FIELD_SPOTTED => tank.spotted = Some(try!(to_u32(value, "spotted"))),
+            FIELD_EXPERIENCE => tank.experience = Some(value),
+            _ => (), // unknown varint field
+        }
+    }
+
+    match (id, battles, wins) {
+        (Some(id), Some(battles), Some(wins)) => {
+            if id > u16::MAX as u64 {
+                return Err(ErrorKind::TankIdOutOfRange(id));
+            }
+            tank.id = id as u16;
+            tank.battles = try!(to_u32(battles, "battles"));
+            tank.wins = try!(to_u32(wins, "wins"));
+            Ok(tank)
+        }
+        _ => Err(ErrorKind::MalformedRecord("missing ID, battles or wins".to_string())),
+    }
+}
+
+fn malformed(error: protobuf::Error) -> ErrorKind {
+    ErrorKind::MalformedRecord(error.to_string())
+}
+
+fn to_u32(value: u64, name: &'static str) -> Result<u32, ErrorKind> {
+    if value <= u32::MAX as u64 {
+        Ok(value as u32)
+    } else {
+        Err(ErrorKind::ValueOutOfRange(name, value))
     }
-    Some(Account { id: account_id as u32, tanks: tanks })
 }
 
-/// Skips account header.
-fn skip_account_header<R: Read>(input: &mut R) -> bool {
-    let mut buffer = [0u8; 1];
-    input.read(&mut buffer).unwrap() == 1 && input.read(&mut buffer).unwrap() == 1
+/// Reads next account statistics.
+#[allow(dead_code)]
+pub fn read_account<R: BufRead>(input: &mut R) -> Result<Option<Account>, Error> {
+    AccountReader::new(input).read_account()
+}
+
+/// Writes account statistics in the format consumed by `read_account`.
+#[allow(dead_code)]
+pub fn write_account<W: Write>(output: &mut W, account: &Account) -> io::Result<()> {
+    try!(output.write_all(&[HEADER_MAGIC, FORMAT_LEGACY]));
+    try!(protobuf::write_uvarint(output, account.id as u64));
+    try!(protobuf::write_uvarint(output, account.tanks.len() as u64));
+    for tank in &account.tanks {
+        try!(protobuf::write_uvarint(output, tank.id as u64));
+        try!(protobuf::write_uvarint(output, tank.battles as u64));
+        try!(protobuf::write_uvarint(output, tank.wins as u64));
+    }
+    Ok(())
 }
 
 #[test]
 fn test_read_account() {
     use std::io::Cursor;
 
-    let account = read_account(&mut Cursor::new(vec![0x3e, 0x3e, 0x03, 0x01, 0x8E, 0x02, 0x9E, 0xA7, 0x05, 0x9D, 0xA7, 0x05])).unwrap();
+    let account = read_account(&mut Cursor::new(vec![0x3e, 0x3e, 0x03, 0x01, 0x8E, 0x02, 0x9E, 0xA7, 0x05, 0x9D, 0xA7, 0x05])).unwrap().unwrap();
     assert_eq!(account.id, 3);
     assert_eq!(account.tanks.len(), 1);
     assert_eq!(account.tanks[0].id, 270);
     assert_eq!(account.tanks[0].battles, 86942);
     assert_eq!(account.tanks[0].wins, 86941);
 }
+
+#[test]
+fn test_write_account() {
+    let mut output = Vec::new();
+    write_account(&mut output, &Account { id: 3, tanks: vec![Tank::new(270, 86942, 86941)] }).unwrap();
+    assert_eq!(output, vec![0x3e, 0x3e, 0x03, 0x01, 0x8E, 0x02, 0x9E, 0xA7, 0x05, 0x9D, 0xA7, 0x05]);
+}
+
+#[test]
+fn test_write_read_account() {
+    use std::io::Cursor;
+
+    let mut output = Vec::new();
+    write_account(&mut output, &Account { id: 1, tanks: vec![] }).unwrap();
+    write_account(&mut output, &Account { id: 500000000, tanks: vec![
+        Tank::new(1, 10, 5),
+        Tank::new(64817, ::std::u32::MAX, 0),
+    ] }).unwrap();
+
+    let mut input = Cursor::new(output);
+    let account = read_account(&mut input).unwrap().unwrap();
+    assert_eq!(account.id, 1);
+    assert_eq!(account.tanks.len(), 0);
+    let account = read_account(&mut input).unwrap().unwrap();
+    assert_eq!(account.id, 500000000);
+    assert_eq!(account.tanks.len(), 2);
+    assert_eq!(account.tanks[1].id, 64817);
+    assert_eq!(account.tanks[1].battles, ::std::u32::MAX);
+    assert_eq!(account.tanks[1].wins, 0);
+    assert!(read_account(&mut input).unwrap().is_none());
+}
+
+#[test]
+fn test_read_account_errors() {
+    use std::io::Cursor;
+
+    let error = read_account(&mut Cursor::new(vec![0x3e, 0x3e, 0x03, 0x01, 0x8E])).unwrap_err();
+    match error.kind { ErrorKind::TruncatedRecord => (), _ => panic!("{:?}", error) }
+    assert_eq!(error.offset, 5);
+    assert!(!error.is_recoverable());
+
+    let error = read_account(&mut Cursor::new(vec![0x3e])).unwrap_err();
+    match error.kind { ErrorKind::TruncatedRecord => (), _ => panic!("{:?}", error) }
+
+    let error = read_account(&mut Cursor::new(vec![0x3e, 0x3e, 0x03, 0x01, 0x80, 0x80, 0x04, 0x01, 0x01])).unwrap_err();
+    match error.kind { ErrorKind::TankIdOutOfRange(65536) => (), _ => panic!("{:?}", error) }
+    assert_eq!(error.offset, 7);
+    assert!(error.is_recoverable());
+
+    let error = read_account(&mut Cursor::new(vec![0x3e, 0x3e, 0x03, 0x01, 0x01, 0x80, 0x80, 0x80, 0x80, 0x10, 0x01])).unwrap_err();
+    match error.kind { ErrorKind::ValueOutOfRange("battles", 4294967296) => (), _ => panic!("{:?}", error) }
+    assert_eq!(error.offset, 10);
+    assert!(error.is_recoverable());
+
+    let error = read_account(&mut Cursor::new(vec![0x3e, 0x3e, 0x80, 0x80, 0x80, 0x80, 0x10, 0x00])).unwrap_err();
+    match error.kind { ErrorKind::ValueOutOfRange("account ID", 4294967296) => (), _ => panic!("{:?}", error) }
+
+    let mut input = vec![0x3e, 0x3e, 0x03];
+    input.extend(vec![0xFF; 11]);
+    let error = read_account(&mut Cursor::new(input)).unwrap_err();
+    match error.kind { ErrorKind::VarintOverflow => (), _ => panic!("{:?}", error) }
+}
+
+#[test]
+fn test_reader_account_index() {
+    use std::io::Cursor;
+
+    let mut output = Vec::new();
+    write_account(&mut output, &Account { id: 1, tanks: vec![] }).unwrap();
+    output.extend(vec![0x3e, 0x3e, 0x02]);
+
+    let mut reader = AccountReader::new(Cursor::new(output));
+    assert_eq!(reader.read_account().unwrap().unwrap().id, 1);
+    assert_eq!(reader.offset(), 4);
+    let error = reader.read_account().unwrap_err();
+    assert_eq!(error.account_index, 1);
+    assert_eq!(error.offset, 7);
+}
+
+#[test]
+fn test_read_account_invalid_header() {
+    use std::io::Cursor;
+
+    let error = read_account(&mut Cursor::new(vec![0x3e, 0x01, 0x03, 0x00])).unwrap_err();
+    match error.kind { ErrorKind::InvalidHeader([0x3e, 0x01]) => (), _ => panic!("{:?}", error) }
+    assert_eq!(error.offset, 2);
+
+    let error = read_account(&mut Cursor::new(vec![0x3e, 0x4d, 0x03, 0x00])).unwrap_err();
+    match error.kind { ErrorKind::UnsupportedFormat(0x4d) => (), _ => panic!("{:?}", error) }
+}
+
+#[test]
+fn test_reader_synchronize() {
+    use std::io::Cursor;
+
+    let mut input = vec![0x01, 0x02];
+    write_account(&mut input, &Account { id: 1, tanks: vec![] }).unwrap();
+    // Corrupt account with a tank ID out of range.
+    input.extend(vec![0x3e, 0x3e, 0x02, 0x01, 0x80, 0x80, 0x04, 0x3e, 0x01]);
+    write_account(&mut input, &Account { id: 3, tanks: vec![Tank::new(1, 2, 1)] }).unwrap();
+
+    let mut reader = AccountReader::new(Cursor::new(input));
+    match reader.read_account().unwrap_err().kind { ErrorKind::InvalidHeader([0x01, 0x02]) => (), _ => panic!() }
+    assert_eq!(reader.read_account().unwrap().unwrap().id, 1);
+    match reader.read_account().unwrap_err().kind { ErrorKind::TankIdOutOfRange(_) => (), _ => panic!() }
+    let account = reader.read_account().unwrap().unwrap();
+    assert_eq!(account.id, 3);
+    assert_eq!(account.tanks[0].battles, 2);
+    assert!(reader.read_account().unwrap().is_none());
+}
+
+#[test]
+fn test_reader_synchronize_keeps_consumed_magic() {
+    use std::io::Cursor;
+
+    // The invalid header consumes the magic of the next one.
+    let mut input = vec![0x01];
+    write_account(&mut input, &Account { id: 1, tanks: vec![] }).unwrap();
+    // The tank ID varint consumes the magic of the next header.
+    input.extend(vec![HEADER_MAGIC, FORMAT_LEGACY, 0x02, 0x01, 0x80, 0x80]);
+    write_account(&mut input, &Account { id: 3, tanks: vec![] }).unwrap();
+
+    let mut reader = AccountReader::new(Cursor::new(input));
+    match reader.read_account().unwrap_err().kind { ErrorKind::InvalidHeader([0x01, 0x3e]) => (), _ => panic!() }
+    assert_eq!(reader.read_account().unwrap().unwrap().id, 1);
+    match reader.read_account().unwrap_err().kind { ErrorKind::TankIdOutOfRange(_) => (), _ => panic!() }
+    assert_eq!(reader.read_account().unwrap().unwrap().id, 3);
+    assert!(reader.read_account().unwrap().is_none());
+}
+
+#[test]
+fn test_account_reader_iterator() {
+    use std::io::{BufReader, Cursor};
+
+    let mut input = Vec::new();
+    for id in 0..100 {
+        let tanks = (0..id).map(|tank_id| Tank::new(tank_id as u16, 1000 * id, 500 * id)).collect();
+        write_account(&mut input, &Account { id: id, tanks: tanks }).unwrap();
+    }
+    let length = input.len() as u64;
+
+    // Small buffer makes values cross the buffer boundary.
+    let mut reader = AccountReader::new(BufReader::with_capacity(7, Cursor::new(input)));
+    for (id, account) in reader.by_ref().enumerate() {
+        let account = account.unwrap();
+        assert_eq!(account.id, id as u32);
+        assert_eq!(account.tanks.len(), id);
+        if id != 0 {
+            assert_eq!(account.tanks[id - 1].battles, 1000 * id as u32);
+        }
+    }
+    assert_eq!(reader.offset(), length);
+}
+
+#[test]
+fn test_account_reader_read_into() {
+    use std::io::Cursor;
+
+    let mut input = Vec::new();
+    write_account(&mut input, &Account { id: 1, tanks: (0..10).map(|id| Tank::new(id, 2, 1)).collect() }).unwrap();
+    write_account(&mut input, &Account { id: 2, tanks: vec![Tank::new(3, 4, 3)] }).unwrap();
+
+    let mut reader = AccountReader::new(Cursor::new(input));
+    let mut account = Account { id: 0, tanks: Vec::new() };
+    assert!(reader.read_into(&mut account).unwrap());
+    let capacity = account.tanks.capacity();
+    assert!(reader.read_into(&mut account).unwrap());
+    assert_eq!(account.id, 2);
+    assert_eq!(account.tanks.len(), 1);
+    assert_eq!(account.tanks[0].id, 3);
+    assert_eq!(account.tanks.capacity(), capacity);
+    assert!(!reader.read_into(&mut account).unwrap());
+}
+
+#[test]
+fn test_account_reader_iterator_stops_on_truncation() {
+    use std::io::Cursor;
+
+    let mut reader = AccountReader::new(Cursor::new(vec![0x3e, 0x3e, 0x03, 0x01, 0x8E]));
+    assert!(reader.next().unwrap().is_err());
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn test_write_read_tagged_account() {
+    use std::io::Cursor;
+
+    let mut tank = Tank::new(270, 86942, 86941);
+    tank.damage_dealt = Some(100000000);
+    tank.frags = Some(70000);
+    tank.experience = Some(0);
+
+    let mut input = Vec::new();
+    write_tagged_account(&mut input, &Account { id: 3, tanks: vec![tank, Tank::new(1, 2, 1)] }).unwrap();
+    write_account(&mut input, &Account { id: 4, tanks: vec![Tank::new(1, 2, 1)] }).unwrap();
+
+    let mut reader = AccountReader::new(Cursor::new(input));
+    let account = reader.read_account().unwrap().unwrap();
+    assert_eq!(account.id, 3);
+    assert_eq!(account.tanks.len(), 2);
+    assert_eq!(account.tanks[0].id, 270);
+    assert_eq!(account.tanks[0].battles, 86942);
+    assert_eq!(account.tanks[0].wins, 86941);
+    assert_eq!(account.tanks[0].damage_dealt, Some(100000000));
+    assert_eq!(account.tanks[0].frags, Some(70000));
+    assert_eq!(account.tanks[0].spotted, None);
+    assert_eq!(account.tanks[0].experience, Some(0));
+    assert_eq!(account.tanks[1].damage_dealt, None);
+    let account = reader.read_account().unwrap().unwrap();
+    assert_eq!(account.id, 4);
+    assert_eq!(account.tanks[0].frags, None);
+}
+
+#[test]
+fn test_decode_tank_skips_unknown_fields() {
+    // ID, unknown fixed32, battles, unknown length-delimited, wins, unknown varint.
+    let record = [
+        0x08, 0x8E, 0x02,
+        0xA5, 0x01, 0x01, 0x02, 0x03, 0x04,
+        0x10, 0x0A,
+        0xAA, 0x01, 0x02, 0xFF, 0xFF,
+        0x18, 0x05,
+        0xB0, 0x01, 0x01,
+    ];
+    let tank = decode_tank(&record).unwrap();
+    assert_eq!(tank.id, 270);
+    assert_eq!(tank.battles, 10);
+    assert_eq!(tank.wins, 5);
+}
+
+#[test]
+fn test_decode_tank_errors() {
+    match decode_tank(&[0x08, 0x01, 0x10, 0x0A]) { Err(ErrorKind::MalformedRecord(_)) => (), other => panic!("{:?}", other) }
+    match decode_tank(&[0x08, 0x01, 0x15, 0x00]) { Err(ErrorKind::MalformedRecord(_)) => (), other => panic!("{:?}", other) }
+    match decode_tank(&[0x08, 0x80, 0x80, 0x04, 0x10, 0x0A, 0x18, 0x05]) {
+        Err(ErrorKind::TankIdOutOfRange(65536)) => (),
+        other => panic!("{:?}", other),
+    }
+}
@@ -4,7 +4,7 @@
 
 use rand::{Rng, thread_rng};
 
-use csr::Csr;
+use csr::{Csr, Index, Value};
 use helpers::AbstractModel;
 
 const GEN_RANGE: f64 = 1.0;
@@ -42,12 +42,12 @@ impl Model {
     /// Makes a step.
     ///
     /// Returns RMSE.
-    pub fn make_step(&mut self, rate: f64, lambda: f64, csr: &Csr) -> f64 {
+    pub fn make_step<C: Index, V: Value>(&mut self, rate: f64, lambda: f64, csr: &Csr<C, V>) -> f64 {
         let mut rmse = 0.0;
         for row_index in 0..csr.row_count() {
             let row = csr.get_row(row_index);
             for column_value in row {
-                rmse += self.train(rate, lambda, row_index, column_value.column, column_value.value);
+                rmse += self.train(rate, lambda, row_index, column_value.column.to_usize(), column_value.value.to_f64());
             }
         }
         (rmse / csr.len() as f64).sqrt()
@@ -92,9 +92,9 @@ impl Model {
     }
 }
 
-impl AbstractModel for Model {
+impl<C: Index, V: Value> AbstractModel<C, V> for Model {
     #[allow(unused_variables)]
-    fn predict(&self, train_matrix: &Csr, row_index: usize, column_index: usize) -> Option<f64> {
+    fn predict(&self, train_matrix: &Csr<C, V>, row_index: usize, column_index: usize) -> Option<f64> {
         Some(self.predict(row_index, column_index))
     }
 }
@@ -1,15 +1,30 @@
 //! SVD-based collaboration filtering for Tankopoisk.
 
+extern crate flate2;
+extern crate memmap;
 extern crate rand;
+extern crate rustc_serialize;
 extern crate time;
+extern crate zstd;
 
+mod accounts;
+mod checksum;
 mod csr;
 mod encyclopedia;
+mod evaluation;
+mod filter;
 mod helpers;
+mod input;
+mod parallel;
 mod protobuf;
+mod split;
 mod stats;
+mod text;
 mod svd;
 
+/// Rating matrix. Single precision is enough for the model and halves memory use.
+type Matrix = csr::Csr<u16, f32>;
+
 /// Minimum battles count.
 const MIN_BATTLES: u32 = 10;
 /// SVD feature count.
@@ -25,23 +40,44 @@ const MAX_ITERATION_COUNT: usize = 500;
 
 #[allow(dead_code)]
 fn main() {
-    let (encyclopedia, train_matrix, test_matrix) = helpers::get_stats(MIN_BATTLES, helpers::identity);
+    let (encyclopedia, train_matrix, test_matrix, accounts): (_, Matrix, Matrix, _) = helpers::get_stats(MIN_BATTLES, helpers::identity);
+    if helpers::run_cross_validation(&train_matrix, &test_matrix, |train_matrix| {
+        let mut model = svd::Model::new(train_matrix.row_count(), encyclopedia.len(), FEATURE_COUNT);
+        train(&mut model, train_matrix, None);
+        model
+    }, helpers::identity) {
+        return;
+    }
     println!("Initializing model.");
     let mut model = svd::Model::new(train_matrix.row_count(), encyclopedia.len(), FEATURE_COUNT);
     println!("Initial evaluation.");
-    let train_error = helpers::evaluate(&model, &train_matrix, &train_matrix, helpers::identity);
-    println!("Train error: {0:.6}.", train_error);
-    let test_error = helpers::evaluate(&model, &train_matrix, &test_matrix, helpers::identity);
-    println!("Test error: {0:.6}.", test_error);
-    train(&mut model, &train_matrix, &test_matrix);
+    let train_report = helpers::evaluate(&model, &train_matrix, &train_matrix, helpers::identity);
+    println!("Train error: {}.", train_report);
+    let test_report = helpers::evaluate(&model, &train_matrix, &test_matrix, helpers::identity);
+    println!("Test error: {}.", test_report);
+    train(&mut model, &train_matrix, Some(&test_matrix));
+    println!("Final evaluation.");
+    let train_report = helpers::evaluate(&model, &train_matrix, &train_matrix, helpers::identity);
+    println!("Train error: {}.", train_report);
+    let test_report = helpers::evaluate(&model, &train_matrix, &test_matrix, helpers::identity);
+    println!("Test error: {}.", test_report);
     let error_distribution = helpers::evaluate_error_distribution(&model, &train_matrix, &test_matrix, helpers::identity);
     println!("Test error distribution:");
     println!("------------------------");
     helpers::print_error_distribution(error_distribution);
+    println!("Test error by tank:");
+    println!("-------------------");
+    test_report.print_columns(&encyclopedia);
+    println!("Test error by tier:");
+    println!("-------------------");
+    test_report.print_tiers(&encyclopedia);
+    helpers::print_recommendations(&model, &train_matrix, &test_matrix, &encyclopedia, &accounts);
 }
 
 /// Trains the model.
-fn train(model: &mut svd::Model, train_matrix: &csr::Csr, test_matrix: &csr::Csr) {
+///
+/// Test error is printed on each step if the test matrix is given.
+fn train(model: &mut svd::Model, train_matrix: &Matrix, test_matrix: Option<&Matrix>) {
     use std::f64;
     use time::now;
 
@@ -52,13 +88,19 @@ fn train(model: &mut svd::Model, train_matrix: &csr::Csr, test_matrix: &csr::Csr
     let mut previous_rmse = f64::INFINITY;
     for step in 0..MAX_ITERATION_COUNT {
         let rmse = model.make_step(RATE, LAMBDA, train_matrix);
-        let train_error = helpers::evaluate(model, &train_matrix, &train_matrix, helpers::identity);
-        let test_error = helpers::evaluate(model, &train_matrix, &test_matrix, helpers::identity);
+        let train_metrics = helpers::evaluate(model, &train_matrix, &train_matrix, helpers::identity).total;
         let drmse = rmse - previous_rmse;
-        println!(
-            "#{0} | {1:.2} sec | E: {2:.6} | dE: {3:.6} | train error: {4:.6} | test error: {5:.6}",
-            step, helpers::get_seconds(start_time) / (step as f32 + 1.0), rmse, -drmse, train_error, test_error,
+        print!(
+            "#{0} | {1:.2} sec | E: {2:.6} | dE: {3:.6} | train MAE: {4:.6} | train RMSE: {5:.6}",
+            step, helpers::get_seconds(start_time) / (step as f32 + 1.0), rmse, -drmse, train_metrics.mae(), train_metrics.rmse(),
         );
+        match test_matrix {
+            Some(test_matrix) => {
+                let test_metrics = helpers::evaluate(model, &train_matrix, test_matrix, helpers::identity).total;
+                println!(" | test MAE: {:.6} | test RMSE: {:.6}", test_metrics.mae(), test_metrics.rmse());
+            }
+            None => println!(),
+        }
         if rmse.is_nan() || drmse.abs() < MIN_DRMSE || drmse > 0.0 {
             break;
         }
@@ -0,0 +1,307 @@
+//! Text statistics import.
+//!
+//! Each row contains account ID, tank ID, battles and wins separated by commas or tabs.
+//! The first row may be a header.
+
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::{u16, u32};
+
+use stats::{Account, AccountSource, Error, ErrorKind, Tank};
+
+/// Single statistics row.
+struct Row {
+    account_id: u32,
+    tank: Tank,
+}
+
+/// Streaming text statistics reader.
+///
+/// Rows of the same account must be consecutive.
+pub struct TextReader<R> {
+    input: R,
+    line: String,
+    offset: u64,
+    account_index: usize,
+    /// Row that starts the next account.
+    pending_row: Option<Row>,
+    /// Invalid rows met while reading the previous account, to be reported next.
+    pending_errors: VecDeque<Error>,
+    /// Whether the first non-empty line has been read.
+    is_started: bool,
+}
+
+impl<R: BufRead> TextReader<R> {
+    pub fn new(input: R) -> Self {
+        TextReader {
+            input: input,
+            line: String::new(),
+            offset: 0,
+            account_index: 0,
+            pending_row: None,
+            pending_errors: VecDeque::new(),
+            is_started: false,
+        }
+    }
+
+    /// Reads all rows and sorts them by account ID.
+    ///
+    /// Use this when rows of the same account are not consecutive.
+    /// Invalid rows are kept to be reported by the sorted reader.
+    pub fn sort(mut self) -> Result<SortedReader, Error> {
+        let mut rows = Vec::new();
+        let mut errors = VecDeque::new();
+
+        loop {
+            match self.read_row() {
+                Ok(Some(row)) => rows.push(row),
+                Ok(None) => break,
+                Err(error) => {
+                    if !error.is_recoverable() {
+                        return Err(error);
+                    }
+                    errors.push_back(error);
+                }
+            }
+        }
+        // Stable sort keeps tanks in the input order.
+        rows.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+
+        Ok(SortedReader { rows: rows, position: 0, errors: errors, offset: self.offset })
+    }
+
+    /// Reads next row skipping empty lines and the header.
+    ///
+    /// Returns `None` at the end of input.
+    fn read_row(&mut self) -> Result<Option<Row>, Error> {
+        loop {
+            self.line.clear();
+            let length = match self.input.read_line(&mut self.line) {
+                Ok(length) => length,
+                Err(error) => return Err(self.error(ErrorKind::Io(error))),
+            };
+            if length == 0 {
+                return Ok(None);
+            }
+            self.offset += length as u64;
+            let is_first_line = !self.is_started;
+            match parse_row(&self.line) {
+                Ok(Some(row)) => {
+                    self.is_started = true;
+                    return Ok(Some(row));
+                }
+                Ok(None) => continue,
+                Err(_) if is_first_line && !starts_with_digit(&self.line) => {
+                    self.is_started = true;
+                    continue;
+                }
+                Err(kind) => {
+                    self.is_started = true;
+                    return Err(self.error(kind));
+                }
+            }
+        }
+    }
+
+    fn error(&self, kind: ErrorKind) -> Error {
+        Error { kind: kind, offset: self.offset, account_index: self.account_index }
+    }
+}
+
+impl<R: BufRead> AccountSource for TextReader<R> {
+    fn read_into(&mut self, account: &mut Account) -> Result<bool, Error> {
+        account.tanks.clear();
+        if let Some(error) = self.pending_errors.pop_front() {
+            return Err(error);
+        }
+        self.account_index += 1;
+
+        let first_row = match self.pending_row.take() {
+            Some(row) => row,
+            None => match try!(self.read_row()) {
+                Some(row) => row,
+                None => return Ok(false),
+            },
+        };
+        account.id = first_row.account_id;
+        account.tanks.push(first_row.tank);
+
+        // Invalid rows are reported after the account, so that its valid rows are not lost.
+        loop {
+            match self.read_row() {
+                Ok(Some(row)) => {
+                    if row.account_id != account.id {
+                        self.pending_row = Some(row);
+                        return Ok(true);
+                    }
+                    account.tanks.push(row.tank);
+                }
+                Ok(None) => return Ok(true),
+                Err(error) => {
+                    let is_recoverable = error.is_recoverable();
+                    self.pending_errors.push_back(error);
+                    if !is_recoverable {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+    }
+
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+/// Reader of the rows sorted in memory.
+pub struct SortedReader {
+    rows: Vec<Row>,
+    position: usize,
+    /// Invalid rows to be reported first.
+    errors: VecDeque<Error>,
+    offset: u64,
+}
+
+impl AccountSource for SortedReader {
+    fn read_into(&mut self, account: &mut Account) -> Result<bool, Error> {
+        account.tanks.clear();
+        if let Some(error) = self.errors.pop_front() {
+            return Err(error);
+        }
+        if self.position == self.rows.len() {
+            return Ok(false);
+        }
+        account.id = self.rows[self.position].account_id;
+        while self.position != self.rows.len() && self.rows[self.position].account_id == account.id {
+            account.tanks.push(self.rows[self.position].tank.clone());
+            self.position += 1;
+        }
+        Ok(true)
+    }
+
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+/// Parses the row.
+///
+/// Returns `None` for an empty line.
+fn parse_row(line: &str) -> Result<Option<Row>, ErrorKind> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let separator = if line.contains('\t') { '\t' } else { ',' };
+    let fields: Vec<&str> = line.split(separator).map(|field| field.trim()).collect();
+    if fields.len() != 4 {
+        return Err(ErrorKind::InvalidRow(format!("expected 4 fields, found {}", fields.len())));
+    }
+    let account_id = try!(parse_field(fields[0], "account ID"));
+    let tank_id = try!(parse_field(fields[1], "tank ID"));
+    let battles = try!(parse_field(fields[2], "battles"));
+    let wins = try!(parse_field(fields[3], "wins"));
+    if account_id > u32::MAX as u64 {
+        return Err(ErrorKind::InvalidRow(format!("account ID {} is out of range", account_id)));
+    }
+    if tank_id > u16::MAX as u64 {
+        return Err(ErrorKind::TankIdOutOfRange(tank_id));
+    }
+    if battles > u32::MAX as u64 || wins > u32::MAX as u64 {
+        return Err(ErrorKind::InvalidRow(format!("battles {} or wins {} are out of range", battles, wins)));
+    }
+    Ok(Some(Row {
+        account_id: account_id as u32,
+        tank: Tank::new(tank_id as u16, battles as u32, wins as u32),
+    }))
+}
+
+/// Parses unsigned integer field.
+fn parse_field(field: &str, name: &str) -> Result<u64, ErrorKind> {
+    field.parse().map_err(|_| ErrorKind::InvalidRow(format!("invalid {}: {:?}", name, field)))
+}
+
+/// Gets whether the line starts with a digit, i.e. it is not a header.
+fn starts_with_digit(line: &str) -> bool {
+    line.trim().chars().next().map_or(false, |c| c.is_digit(10))
+}
+
+#[cfg(test)]
+fn read_all<S: AccountSource>(source: &mut S) -> Vec<Account> {
+    let mut accounts = Vec::new();
+    loop {
+        let mut account = Account { id: 0, tanks: Vec::new() };
+        if !source.read_into(&mut account).unwrap() {
+            return accounts;
+        }
+        accounts.push(account);
+    }
+}
+
+#[test]
+fn test_text_reader() {
+    use std::io::Cursor;
+
+    let input = "account_id,tank_id,battles,wins\n1,270,10,5\n1, 33, 20, 11\n\n2\t49\t3\t3\n";
+    let accounts = read_all(&mut TextReader::new(Cursor::new(input.as_bytes())));
+    assert_eq!(accounts.len(), 2);
+    assert_eq!(accounts[0].id, 1);
+    assert_eq!(accounts[0].tanks.len(), 2);
+    assert_eq!(accounts[0].tanks[1].id, 33);
+    assert_eq!(accounts[0].tanks[1].battles, 20);
+    assert_eq!(accounts[0].tanks[1].wins, 11);
+    assert_eq!(accounts[1].id, 2);
+    assert_eq!(accounts[1].tanks[0].id, 49);
+}
+
+#[test]
+fn test_text_reader_invalid_row() {
+    use std::io::Cursor;
+
+    let mut reader = TextReader::new(Cursor::new("1,270,10,5\n2,x,1,1\n3,70000,1,1\n".as_bytes()));
+    let mut account = Account { id: 0, tanks: Vec::new() };
+    assert!(reader.read_into(&mut account).unwrap());
+    assert_eq!(account.id, 1);
+    assert_eq!(account.tanks[0].id, 270);
+    match reader.read_into(&mut account).unwrap_err().kind {
+        ErrorKind::InvalidRow(_) => (),
+        kind => panic!("{:?}", kind),
+    }
+    match reader.read_into(&mut account).unwrap_err().kind {
+        ErrorKind::TankIdOutOfRange(70000) => (),
+        kind => panic!("{:?}", kind),
+    }
+    assert!(!reader.read_into(&mut account).unwrap());
+}
+
+#[test]
+fn test_text_reader_invalid_row_inside_account() {
+    use std::io::Cursor;
+
+    let mut reader = TextReader::new(Cursor::new("1,270,10,5\n1,x,1,1\n1,33,20,11\n2,49,3,3\n".as_bytes()));
+    let mut account = Account { id: 0, tanks: Vec::new() };
+    assert!(reader.read_into(&mut account).unwrap());
+    assert_eq!(account.id, 1);
+    assert_eq!(account.tanks.iter().map(|tank| tank.id).collect::<Vec<u16>>(), vec![270, 33]);
+    assert!(reader.read_into(&mut account).is_err());
+    assert!(reader.read_into(&mut account).unwrap());
+    assert_eq!(account.id, 2);
+    assert!(!reader.read_into(&mut account).unwrap());
+}
+
+#[test]
+fn test_sorted_reader() {
+    use std::io::Cursor;
+
+    let input = "2,1,10,5\n1,2,10,5\n2,3,10,5\nbad row\n1,4,10,5\n";
+    let mut reader = TextReader::new(Cursor::new(input.as_bytes())).sort().unwrap();
+    let mut account = Account { id: 0, tanks: Vec::new() };
+    assert!(reader.read_into(&mut account).is_err());
+    let accounts = read_all(&mut reader);
+    assert_eq!(accounts.len(), 2);
+    assert_eq!(accounts[0].id, 1);
+    assert_eq!(accounts[0].tanks.iter().map(|tank| tank.id).collect::<Vec<u16>>(), vec![2, 4]);
+    assert_eq!(accounts[1].id, 2);
+    assert_eq!(accounts[1].tanks.iter().map(|tank| tank.id).collect::<Vec<u16>>(), vec![1, 3]);
+    assert_eq!(reader.offset(), input.len() as u64);
+}